@@ -0,0 +1,145 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+const ESC: char = '\u{1b}';
+
+/// The SGR sequence that turns off all styling.
+pub const RESET: &str = "\u{1b}[0m";
+
+/// Iterates over graphemes like `UnicodeSegmentation::grapheme_indices`, but
+/// with every ANSI escape sequence as a single item when `ansi` is set.
+pub struct Graphemes<'a> {
+    buf: &'a str,
+    pos: usize,
+    ansi: bool,
+}
+
+pub fn graphemes(buf: &str, ansi: bool) -> Graphemes {
+    Graphemes {
+        buf,
+        pos: 0,
+        ansi,
+    }
+}
+
+impl<'a> Iterator for Graphemes<'a> {
+    type Item = (usize, &'a str);
+
+    fn next(&mut self) -> Option<(usize, &'a str)> {
+        let rest = &self.buf[self.pos..];
+        let len = match escape_len(rest) {
+            Some(len) if self.ansi => len,
+            _ => UnicodeSegmentation::graphemes(rest, true).next()?.len(),
+        };
+
+        let start = self.pos;
+        self.pos += len;
+        Some((start, &self.buf[start..self.pos]))
+    }
+}
+
+/// True for items from `Graphemes` that are escape sequences, which take up
+/// no space on screen.
+pub fn is_escape(grapheme: &str) -> bool {
+    grapheme.len() > 1 && grapheme.starts_with(ESC)
+}
+
+/// True for escape sequences that set the style (SGR), the only ones that
+/// are passed on to the terminal.
+pub fn is_style(escape: &str) -> bool {
+    is_escape(escape) && escape.ends_with('m')
+}
+
+/// The number of graphemes in `buf` that take up space on screen.
+pub fn width(buf: &str) -> usize {
+    graphemes(buf, true).filter(|&(_, g)| !is_escape(g)).count()
+}
+
+/// The length of the CSI escape sequence at the start of `buf`, if any.
+fn escape_len(buf: &str) -> Option<usize> {
+    let bytes = buf.as_bytes();
+    if bytes.len() < 2 || bytes[0] != ESC as u8 || bytes[1] != b'[' {
+        return None;
+    }
+
+    // Parameter and intermediate bytes, then a final byte.
+    for (i, &b) in bytes.iter().enumerate().skip(2) {
+        match b {
+            0x20..=0x3f => continue,
+            0x40..=0x7e => return Some(i + 1),
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// The styles set by SGR sequences seen so far, so they can be re-applied
+/// after something else has changed the style, e.g. at the start of a row.
+#[derive(Debug, Default)]
+pub struct Style {
+    active: String,
+}
+
+impl Style {
+    pub fn new() -> Style {
+        Style::default()
+    }
+
+    /// Takes the SGR sequence `escape` into account.
+    pub fn apply(&mut self, escape: &str) {
+        let params = &escape[2..escape.len() - 1];
+        let first = params.split(';').next().unwrap_or("");
+        if first.is_empty() || first == "0" {
+            self.active.clear();
+        }
+        if params.split(';').any(|p| !p.is_empty() && p != "0") {
+            self.active.push_str(escape);
+        }
+    }
+
+    /// The sequences needed to turn the active style back on.
+    pub fn active(&self) -> &str {
+        &self.active
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_graphemes() {
+        let s = "a\u{1b}[1;31mbc\u{1b}[0m";
+        let with: Vec<&str> = graphemes(s, true).map(|(_, g)| g).collect();
+        assert_eq!(with, vec!["a", "\u{1b}[1;31m", "b", "c", "\u{1b}[0m"]);
+
+        let without = graphemes(s, false).count();
+        assert_eq!(without, s.chars().count());
+    }
+
+    #[test]
+    fn test_incomplete_escape() {
+        let s = "\u{1b}[31";
+        assert_eq!(graphemes(s, true).count(), 4);
+        assert_eq!(width(s), 4);
+    }
+
+    #[test]
+    fn test_width() {
+        assert_eq!(width("\u{1b}[32mok\u{1b}[m"), 2);
+        assert_eq!(width("e\u{301}\u{1b}[1mx"), 2);
+    }
+
+    #[test]
+    fn test_style() {
+        let mut style = Style::new();
+        style.apply("\u{1b}[1m");
+        style.apply("\u{1b}[31m");
+        assert_eq!(style.active(), "\u{1b}[1m\u{1b}[31m");
+
+        style.apply("\u{1b}[0;32m");
+        assert_eq!(style.active(), "\u{1b}[0;32m");
+
+        style.apply("\u{1b}[m");
+        assert_eq!(style.active(), "");
+    }
+}
@@ -1,13 +1,22 @@
 use clap::{App as ClapApp, Arg, ArgMatches};
 use std::fs::File;
-use std::io::stdin;
+use std::io::{self, stdin};
 
+use error::{Error, Result};
 use reader::{InputReader, StdinCursor};
 
 #[derive(Debug)]
 pub enum InputType {
     Stdin(StdinCursor),
-    Files(Vec<File>),
+    Files(Vec<InputFile>),
+}
+
+#[derive(Debug)]
+pub struct InputFile {
+    pub name: String,
+    /// Why the file couldn't be opened, if it couldn't. It stays in the
+    /// list of files, showing the error instead of its contents.
+    pub file: Result<File>,
 }
 
 pub struct App {
@@ -17,29 +26,52 @@ pub struct App {
 impl App {
     pub fn new() -> App {
         App {
-            matches: clap_app().get_matches(),
+            matches: clap_app()
+                .get_matches_from(expand_plus_args(std::env::args().collect())),
         }
     }
 
-    pub fn input_reader(&self) -> InputReader {
-        let files = self
+    /// The line given with `--line` or `+N`.
+    pub fn start_line(&self) -> Option<u64> {
+        self.matches
+            .value_of("line")
+            .and_then(|line| line.parse().ok())
+    }
+
+    /// Reads the files given, or stdin if there are none. Reports the
+    /// files that can't be opened, `None` if none of them can.
+    pub fn input_reader(&self) -> Option<InputReader> {
+        let files: Vec<InputFile> = self
             .matches
             .values_of("FILE")
             .map(|values| {
                 values
-                    .map(|filename| File::open(filename).unwrap())
+                    .map(|filename| InputFile {
+                        name: filename.to_string(),
+                        file: open(filename),
+                    })
                     .collect()
             })
             .unwrap_or_else(|| Vec::new());
 
+        for input_file in &files {
+            if let Err(e) = &input_file.file {
+                eprintln!("less2: {}", e);
+            }
+        }
+
         return if files.len() > 0 {
-            InputReader::new(InputType::Files(files))
+            if files.iter().any(|f| f.file.is_ok()) {
+                Some(InputReader::new(InputType::Files(files)))
+            } else {
+                None
+            }
         } else {
             let stdin = stdin();
             if termion::is_tty(&stdin) {
                 eprintln!("Expected a file or input over stdin.");
             }
-            self.stdin_reader()
+            Some(self.stdin_reader())
         };
     }
 
@@ -59,6 +91,23 @@ impl App {
     }
 }
 
+/// Opens `path` for viewing. Unlike `File::open`, fails for directories.
+pub fn open(path: &str) -> Result<File> {
+    let opened = File::open(path).and_then(|file| {
+        if file.metadata()?.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::IsADirectory,
+                "Is a directory",
+            ));
+        }
+        Ok(file)
+    });
+    opened.map_err(|error| Error::Open {
+        path: path.to_string(),
+        error,
+    })
+}
+
 fn clap_app() -> ClapApp<'static, 'static> {
     ClapApp::new("less2")
         .version("0.0.1")
@@ -76,8 +125,132 @@ fn clap_app() -> ClapApp<'static, 'static> {
                 .long("no-wrap")
                 .short("S")
                 .help("Don't wrap long lines.")
-                .long_help("Don't wrap long lines."),
+                .long_help(
+                    "Don't wrap long lines. Can be toggled while running \
+                     with -S.",
+                ),
+        )
+        .arg(
+            Arg::with_name("line-numbers")
+                .long("line-numbers")
+                .short("N")
+                .help("Show line numbers.")
+                .long_help(
+                    "Show line numbers. Can be toggled while running with -N.",
+                ),
+        )
+        .arg(
+            Arg::with_name("raw-control-chars")
+                .long("raw-control-chars")
+                .short("R")
+                .help("Show ANSI colors in the input.")
+                .long_help(
+                    "Pass ANSI color sequences in the input on to the \
+                     terminal instead of showing them as text. Can be \
+                     toggled while running with -R.",
+                ),
+        )
+        .arg(
+            Arg::with_name("ignore-case")
+                .long("ignore-case")
+                .short("i")
+                .help("Ignore case in searches without upper case letters.")
+                .long_help(
+                    "Ignore case in searches, unless the pattern has upper \
+                     case letters. Can be toggled while running with -i, or \
+                     for a single search with Ctrl-T.",
+                ),
+        )
+        .arg(
+            Arg::with_name("IGNORE-CASE")
+                .long("IGNORE-CASE")
+                .short("I")
+                .help("Ignore case in all searches.")
+                .long_help(
+                    "Ignore case in all searches. Can be toggled while \
+                     running with -I, or for a single search with Ctrl-T.",
+                ),
+        )
+        .arg(
+            Arg::with_name("wrap-search")
+                .long("wrap-search")
+                .help("Let n and N wrap around the input.")
+                .long_help(
+                    "When there are no more matches in the direction of the \
+                     search, let n and N go on from the first or last match \
+                     instead of stopping.",
+                ),
+        )
+        .arg(
+            Arg::with_name("prompt")
+                .long("prompt")
+                .short("P")
+                .value_name("PROMPT")
+                .help("Format of the status line.")
+                .long_help(
+                    "Format of the status line. %f, %i and %m are the file \
+                     name, index and count. %lt, %lb and %L are the top and \
+                     bottom line and the line count, %bt, %bb and %B the top \
+                     and bottom byte offset and the size, %pt and %pb the \
+                     top and bottom percentage, and %o the mode flags. \
+                     ?X...:... shows the first part if X is known, else the \
+                     second, ending with a '.'. \\ escapes a character.",
+                ),
+        )
+        .arg(
+            Arg::with_name("follow")
+                .long("follow")
+                .help("Follow the end of the input as it grows.")
+                .long_help(
+                    "Start at the end of the input and keep following it as \
+                     data is appended, like `tail -f`. Any command stops \
+                     following; press F to start again.",
+                ),
+        )
+        .arg(
+            Arg::with_name("line")
+                .long("line")
+                .value_name("N")
+                .help("Start at line N. Same as +N.")
+                .long_help("Start at line N. Can also be given as +N.")
+                .validator(|n| match n.parse::<u64>() {
+                    Ok(_) => Ok(()),
+                    Err(_) => Err(format!("'{}' is not a line number", n)),
+                }),
         )
         .help_message("Print this help message.")
         .version_message("Show version information.")
 }
+
+/// Rewrites less style `+N` arguments to `--line=N`, which clap understands.
+fn expand_plus_args(args: Vec<String>) -> Vec<String> {
+    args.into_iter()
+        .map(|arg| {
+            let is_line = arg.len() > 1
+                && arg.starts_with('+')
+                && arg[1..].chars().all(|c| c.is_ascii_digit());
+            if is_line {
+                format!("--line={}", &arg[1..])
+            } else {
+                arg
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open() {
+        let error = open("/nonexistent/file").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "/nonexistent/file: No such file or directory"
+        );
+        let error = open("/").unwrap_err();
+        assert_eq!(error.to_string(), "/: Is a directory");
+        assert!(open("Cargo.toml").is_ok());
+    }
+}
@@ -1,41 +1,197 @@
 use grep::matcher::Match;
+use grep::regex::{self, RegexMatcher};
 
-use error::Result;
+use std::collections::HashMap;
+use std::mem;
+use std::path::PathBuf;
+use std::str;
+use std::time::Duration;
+
+use ansi;
+use error::{self, Error, Result};
+use highlight::{self, Highlight};
 use input::{Command, CommandLine, UserInput};
-use reader::{BiBufReader, FileSwitcher, InputReader, Search, ValidReader};
+use interrupt;
+use message::{MessageKind, MessageQueue};
+use printer::Gutter;
+use reader::{
+    BiBufReader, FileSwitcher, Filtering, InputReader, LineLookup, Search,
+    Streaming, ValidReader,
+};
+use searcher::{BackgroundSearch, CaseMode, SearchOptions};
+use status::{Status, DEFAULT_PROMPT};
 use util;
 
+/// How often a followed file, or a pipe that is still open, is checked for
+/// appended data.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How often matches are collected from a search that is still running.
+const SEARCH_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
 pub struct Controller {
     pub reader: BiBufReader<ValidReader<InputReader>>,
     pub quit: bool,
     command_line: CommandLine,
     pub matches: Vec<(u64, Match)>,
+    following: bool,
+    last_size: u64,
+    last_eof: bool,
+    /// Offsets of the first byte on screen and the byte after the last one.
+    page_bounds: (u64, u64),
+    prompt: String,
+    /// Columns scrolled to the right when lines aren't wrapped.
+    h_offset: usize,
+    /// Set while an incremental search has moved the view.
+    search_origin: Option<SearchOrigin>,
+    /// Why the pattern being typed isn't a valid regex.
+    search_error: Option<String>,
+    /// The search filling `matches`, until it is done.
+    search: Option<BackgroundSearch>,
+    /// Where to go on searching from when the input is followed, once the
+    /// search is done.
+    resume_point: Option<u64>,
+    /// A jump to the next match, waiting for the search to find it.
+    pending_jump: Option<MatchJump>,
+    /// A line to jump to, waiting for the index to get to it.
+    pending_line: Option<u64>,
+    /// What the last search looked for, to search other files for when
+    /// switching to them.
+    matcher: Option<RegexMatcher>,
+    /// How the files other than the current one were left, kept from when
+    /// they were last shown.
+    file_views: HashMap<usize, FileView>,
+    /// Where the view was when a filter was set, to move to once the
+    /// filter has got that far.
+    filter_anchor: Option<u64>,
+    /// Patterns pinned with `:hl`, in the order they were pinned.
+    highlights: Vec<Highlight>,
+    /// Shown instead of the status line, one until each key press.
+    messages: MessageQueue,
+    /// Shown instead of the page until the next key press.
+    overlay: Option<String>,
+    /// The match last jumped to, an index into `matches`.
+    current_match: Option<usize>,
+    /// `n` and `N` go on from the other end when there are no more matches.
+    wrap_search: bool,
+    /// Whether lines are wrapped in a file that hasn't been shown yet, as
+    /// given on the command line.
+    default_wrap: bool,
+    /// The last jump went on from the other end, until the next key press.
+    search_wrapped: bool,
+}
+
+/// The view before an incremental search started, to go back to if it's
+/// cancelled.
+struct SearchOrigin {
+    offset: u64,
+    h_offset: usize,
+    matches: Vec<(u64, Match)>,
+    matcher: Option<RegexMatcher>,
+    resume_point: Option<u64>,
+}
+
+/// A jump to the next match in some direction.
+#[derive(Debug, Clone, Copy)]
+struct MatchJump {
+    /// The offset in the input to look from, `None` to look from the start
+    /// of the file when going forward, or from its end.
+    from: Option<u64>,
+    /// The match the view is at, to step through every match in its line
+    /// before going on to the next line.
+    after: Option<usize>,
+    is_forward: bool,
+    /// Go on into the next or previous file when there are no more matches.
+    across_files: bool,
+    /// The file the jump started in, to go back to if there are no more
+    /// matches in any file.
+    start_file: usize,
+}
+
+/// How a file that isn't shown was left, to bring back when it is
+/// switched to again.
+struct FileView {
+    /// The offset in the input at the top of the screen.
+    offset: u64,
+    h_offset: usize,
+    wrap: bool,
+    /// `None` if the file is to be searched again, for another pattern.
+    matches: Option<FileMatches>,
+}
+
+/// The matches of the last search in a file that isn't shown.
+struct FileMatches {
+    matches: Vec<(u64, Match)>,
+    /// Still running if the file was left before it was done.
+    search: Option<BackgroundSearch>,
+    resume_point: Option<u64>,
+    current_match: Option<usize>,
 }
 
 impl Controller {
     pub fn new(input_reader: InputReader, wrap: bool) -> Controller {
+        let last_eof = input_reader.is_eof();
         Controller {
             reader: BiBufReader::new(ValidReader::new(input_reader), wrap),
             quit: false,
             command_line: CommandLine::new(),
             matches: Vec::new(),
+            following: false,
+            last_size: 0,
+            last_eof,
+            page_bounds: (0, 0),
+            prompt: String::from(DEFAULT_PROMPT),
+            h_offset: 0,
+            search_origin: None,
+            search_error: None,
+            search: None,
+            resume_point: None,
+            pending_jump: None,
+            pending_line: None,
+            matcher: None,
+            file_views: HashMap::new(),
+            filter_anchor: None,
+            highlights: Vec::new(),
+            messages: MessageQueue::new(),
+            overlay: None,
+            current_match: None,
+            wrap_search: false,
+            search_wrapped: false,
+            default_wrap: wrap,
         }
     }
 
     pub fn update(&mut self, input: &UserInput) -> Result<()> {
+        self.messages.dismiss();
+        self.overlay = None;
+        self.search_wrapped = false;
         let command = self.command_line.parse_input(input);
+        self.update_command(command)
+    }
+
+    pub fn update_command(&mut self, command: Command) -> Result<()> {
+        // Any other command takes us out of follow mode.
+        if command != Command::NoOp && command != Command::Follow {
+            self.set_following(false);
+        }
+        // As does waiting for a match.
+        if command != Command::NoOp {
+            self.pending_jump = None;
+            self.pending_line = None;
+            self.filter_anchor = None;
+        }
 
         match command {
             Command::UpOneLine => self.reader.up_n_lines(1)?,
-            Command::DownOneLine => self.reader.down_n_lines(1)?,
+            Command::DownOneLine => self.down_n_lines(1)?,
             Command::DownHalfScreen => {
-                self.reader.down_n_lines(util::screen_height_half())?
+                self.down_n_lines(util::screen_height_half())?
             }
             Command::UpHalfScreen => {
                 self.reader.up_n_lines(util::screen_height_half())?
             }
             Command::DownOneScreen => {
-                self.reader.down_n_lines(util::screen_height())?
+                self.down_n_lines(util::screen_height())?
             }
             Command::UpOneScreen => {
                 self.reader.up_n_lines(util::screen_height())?
@@ -43,17 +199,92 @@ impl Controller {
             Command::JumpBeginning => self.reader.jump_percentage(0)?,
             Command::JumpEnd => self.reader.jump_end()?,
             Command::JumpPercent(p) => self.reader.jump_percentage(p)?,
+            Command::JumpLine(line) => self.jump_line(line)?,
+
+            Command::ScrollLeft(n) => {
+                let n = self.scroll_amount(n);
+                self.h_offset = self.h_offset.saturating_sub(n);
+            }
+            Command::ScrollRight(n) if !self.reader.wrap => {
+                self.h_offset += self.scroll_amount(n);
+            }
 
             Command::JumpNextMatch(is_forward) => {
-                self.jump_next_match(is_forward)
+                self.jump_next_match(is_forward, false)
+            }
+            Command::JumpNextMatchAcrossFiles(is_forward) => {
+                self.jump_next_match(is_forward, true)
             }
 
-            Command::Search(pattern, is_forward) => {
-                self.find_matches(&pattern);
-                self.jump_next_match(is_forward)
+            Command::Search(pattern, is_forward, options) => {
+                self.return_to_search_origin()?;
+                match self.find_matches(&pattern, options) {
+                    Ok(()) => {
+                        self.search_origin = None;
+                        self.jump_next_match(is_forward, false)
+                    }
+                    // Back to the search before, as if cancelled.
+                    Err(e) => {
+                        self.cancel_search()?;
+                        self.show_error(&Error::from(e))
+                    }
+                }
+            }
+            Command::SearchPreview(pattern, is_forward, options) => {
+                self.preview_search(&pattern, is_forward, options)?
+            }
+            Command::SearchCancel => self.cancel_search()?,
+            Command::Filter(pattern, invert, options) => {
+                self.set_filter(&pattern, invert, options)?
+            }
+
+            Command::Highlight(pattern, color, options) => {
+                self.add_highlight(&pattern, color, options)?
+            }
+            Command::ListHighlights => {
+                let list = highlight::list(&self.highlights);
+                self.messages.push(MessageKind::Info, list)
+            }
+            Command::RemoveHighlight(n) => self.remove_highlight(n),
+
+            Command::Follow => self.follow()?,
+            Command::ToggleOption('N') => {
+                self.reader.line_numbers = !self.reader.line_numbers
+            }
+            Command::ToggleOption('S') => {
+                self.reader.wrap = !self.reader.wrap;
+                // Keep the line under the top row in place, wrapped rows
+                // above it in the same line are shown again.
+                self.reader.jump_line_start()?
+            }
+            Command::ToggleOption('R') => self.reader.ansi = !self.reader.ansi,
+            Command::ToggleOption('i') => {
+                self.toggle_case_mode(CaseMode::Smart)
+            }
+            Command::ToggleOption('I') => {
+                self.toggle_case_mode(CaseMode::Insensitive)
             }
 
-            Command::NextFile => self.next_file(),
+            Command::NextFile => self.next_file()?,
+            Command::PreviousFile => {
+                let count = self.reader.file_count();
+                let index = (self.reader.file_index() + count - 1) % count;
+                self.switch_file(index)?
+            }
+            Command::JumpFile(n) => {
+                if n >= 1 && n <= self.reader.file_count() {
+                    self.switch_file(n - 1)?
+                } else {
+                    let text = format!("No file {}", n);
+                    self.messages.push(MessageKind::Error, text)
+                }
+            }
+            Command::OpenFile(path) => self.open_file(&path)?,
+            Command::CloseFile => self.close_file()?,
+            Command::ListFiles => {
+                let names = self.reader.file_names();
+                self.overlay = Some(file_list(&names, self.reader.file_index()))
+            }
             Command::Quit => {
                 self.quit = true;
             }
@@ -63,64 +294,1072 @@ impl Controller {
         Ok(())
     }
 
+    /// Moves down `n` rows, staying put at the end of the input.
+    fn down_n_lines(&mut self, n: usize) -> Result<()> {
+        match self.reader.down_n_lines(n) {
+            Err(Error::EndOfInput) => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Pins the view to the end of the input, and keeps it there as data
+    /// is appended until another command is given.
+    pub fn follow(&mut self) -> Result<()> {
+        self.set_following(true);
+        self.pending_line = None;
+        self.resume_searches();
+        self.last_size = self.reader.size()?;
+        self.reader.jump_end()
+    }
+
+    /// Goes on with the filter and the searches that are done, into what is
+    /// appended to the input while it's followed.
+    fn resume_searches(&mut self) {
+        self.reader.resume_filter();
+        if let (Some(from), Some(matcher)) =
+            (self.resume_point.take(), &self.matcher)
+        {
+            self.matches.retain(|(offset, _)| *offset < from);
+            self.current_match =
+                self.current_match.filter(|&i| i < self.matches.len());
+            self.search = self.reader.search(matcher.clone(), from);
+        }
+        for highlight in &mut self.highlights {
+            highlight.resume(&self.reader);
+        }
+    }
+
+    /// While following, the background threads reading the input wait for
+    /// more of it at its end.
+    fn set_following(&mut self, following: bool) {
+        self.following = following;
+        self.reader.set_following(following);
+    }
+
+    /// How long the main loop may block waiting for input before `poll`
+    /// should be called. `None` means there is nothing to poll for.
+    pub fn poll_interval(&self) -> Option<Duration> {
+        if self.search.is_some()
+            || self.pending_line.is_some()
+            || self.highlights.iter().any(|h| h.is_searching())
+        {
+            Some(SEARCH_POLL_INTERVAL)
+        } else if self.following || !self.last_eof || !self.reader.is_eof() {
+            Some(POLL_INTERVAL)
+        } else {
+            None
+        }
+    }
+
+    /// Stops what Ctrl-C was meant for, if it was pressed: following, and
+    /// the search and jumps being waited for. Highlights and the filter
+    /// go on in the background, as nothing waits for them. Returns true if
+    /// it was pressed.
+    pub fn check_interrupt(&mut self) -> bool {
+        if !interrupt::take() {
+            return false;
+        }
+        self.set_following(false);
+        self.stop_search();
+        self.filter_anchor = None;
+        self.pending_line = None;
+        true
+    }
+
+    /// Re-flows the page for a new terminal size, keeping the same line at
+    /// the top of the screen.
+    pub fn resize(&mut self) -> Result<()> {
+        if self.following {
+            return self.reader.jump_end();
+        }
+        self.reader.jump_line_start()
+    }
+
+    /// Checks for changes that didn't come from user input. Returns true if
+    /// the screen needs to be redrawn.
+    pub fn poll(&mut self) -> Result<bool> {
+        let mut searched = self.poll_search();
+        for highlight in &mut self.highlights {
+            searched |= highlight.poll();
+        }
+
+        let eof = self.reader.is_eof();
+        let size = self.reader.size()?;
+        let anchored = self.jump_to_filter_anchor()?;
+        let jumped = self.poll_line_jump()?;
+        if size == self.last_size && eof == self.last_eof {
+            return Ok(searched || anchored || jumped);
+        }
+
+        self.last_size = size;
+        self.last_eof = eof;
+        if self.following {
+            self.reader.jump_end()?;
+        }
+        Ok(true)
+    }
+
     pub fn page(&mut self) -> (u64, Vec<u8>) {
-        return match self.reader.page() {
+        let h_offset = self.h_offset();
+        let page = match self.reader.page(h_offset) {
             Ok(s) => s,
             Err(e) => {
-                eprintln!("{}", e);
+                self.show_error(&e.context("read the page"));
                 (1, Vec::new())
             }
         };
+        self.page_bounds = (page.0, page.0 + self.displayed_len(&page.1));
+        page
+    }
+
+    pub fn command_line_text(&mut self) -> String {
+        if self.command_line.is_idle() {
+            if let Some(message) = self.messages.current() {
+                return message.text.clone();
+            }
+            let prompt = self.prompt.clone();
+            return self.status().format(&prompt);
+        }
+        match &self.search_error {
+            Some(error) => format!("{}  [{}]", self.command_line.text(), error),
+            None => self.command_line.text(),
+        }
     }
 
-    pub fn command_line_text(&self) -> String {
-        return self.command_line.text();
+    /// Text to show instead of the page, e.g. the list of files, or why
+    /// the current file couldn't be opened.
+    pub fn overlay(&self) -> Option<String> {
+        if let Some(overlay) = &self.overlay {
+            return Some(overlay.clone());
+        }
+        self.reader.file_error().map(|e| e.to_string())
+    }
+
+    /// Where the cursor is in `command_line_text`, `None` when nothing
+    /// is being typed and it goes at the end.
+    pub fn command_line_cursor(&self) -> Option<usize> {
+        if self.command_line.is_idle() {
+            None
+        } else {
+            Some(self.command_line.cursor())
+        }
+    }
+
+    /// How the message in `command_line_text` is shown, `None` when it
+    /// isn't a message.
+    pub fn message_kind(&self) -> Option<MessageKind> {
+        if !self.command_line.is_idle() {
+            return None;
+        }
+        self.messages.current().map(|message| message.kind)
+    }
+
+    /// Shows `error` instead of the status line until a key is pressed.
+    pub fn show_error(&mut self, error: &Error) {
+        self.messages.push(MessageKind::Error, error.to_string());
+    }
+
+    /// Keeps the search and command history in `path` between sessions.
+    pub fn set_history_file(&mut self, path: PathBuf) {
+        self.command_line.set_history_file(path);
+    }
+
+    /// Sets the format of the status line, see `Status::format`.
+    pub fn set_prompt(&mut self, prompt: &str) {
+        self.prompt = prompt.to_string();
+    }
+
+    pub fn status(&mut self) -> Status {
+        let (top, bottom) = self.page_bounds;
+        let top_line = self.reader.line_of_offset(top);
+        let bottom_line = if bottom > top {
+            self.reader.line_of_offset(bottom - 1)
+        } else {
+            top_line
+        };
+
+        Status {
+            file_name: self.reader.file_name().to_string(),
+            file_index: self.reader.file_index() + 1,
+            file_count: self.reader.file_count(),
+            top_line,
+            bottom_line,
+            line_count: self.reader.line_count(),
+            top_offset: top,
+            bottom_offset: bottom,
+            size: self.reader.size().unwrap_or(0),
+            streaming: !self.reader.is_eof(),
+            following: self.following,
+            wrap: self.reader.wrap,
+            searched: self.search.as_ref().map(|s| s.searched()),
+            filtered: self.reader.is_filtered(),
+            current_match: self.current_match.map(|i| i + 1),
+            match_count: if self.search.is_some() || !self.matches.is_empty() {
+                Some(self.matches.len())
+            } else {
+                None
+            },
+            search_wrapped: self.search_wrapped,
+            indexing: self.pending_line,
+        }
+    }
+
+    /// How many bytes of `page` fit on the screen.
+    fn displayed_len(&self, page: &[u8]) -> u64 {
+        let rows = std::cmp::max(1, util::screen_height() - 1);
+        unsafe {
+            util::nth_newline_pos(
+                rows,
+                str::from_utf8_unchecked(page),
+                self.reader.wrap_width(),
+                self.reader.ansi,
+            ) as u64
+        }
     }
 
     pub fn is_wrap(&self) -> bool {
         self.reader.wrap
     }
 
-    fn find_matches(&mut self, pattern: &str) {
+    /// Columns scrolled to the right, always 0 when lines are wrapped.
+    pub fn h_offset(&self) -> usize {
+        if self.reader.wrap {
+            0
+        } else {
+            self.h_offset
+        }
+    }
+
+    /// Columns to scroll horizontally for a count of `n`, where 0 means half
+    /// a screen.
+    fn scroll_amount(&self, n: usize) -> usize {
+        if n > 0 {
+            return n;
+        }
+        let (screen_width, _) = util::screen_width_height();
+        let text_width =
+            util::text_width(screen_width, self.reader.line_numbers);
+        std::cmp::max(1, text_width as usize / 2)
+    }
+
+    pub fn set_line_numbers(&mut self, line_numbers: bool) {
+        self.reader.line_numbers = line_numbers;
+    }
+
+    /// Passes ANSI color sequences in the input on to the terminal.
+    pub fn set_ansi(&mut self, ansi: bool) {
+        self.reader.ansi = ansi;
+    }
+
+    pub fn is_ansi(&self) -> bool {
+        self.reader.ansi
+    }
+
+    /// Lets `n` and `N` go on from the first or last match when there are
+    /// no more in that direction.
+    pub fn set_wrap_search(&mut self, wrap_search: bool) {
+        self.wrap_search = wrap_search;
+    }
+
+    /// How searches treat letter case unless changed in the prompt.
+    pub fn set_case_mode(&mut self, case: CaseMode) {
+        self.command_line.default_search_options.case = case;
+    }
+
+    /// Turns `case` on, or back to case sensitive if it already is on.
+    fn toggle_case_mode(&mut self, case: CaseMode) {
+        let options = &mut self.command_line.default_search_options;
+        options.case = if options.case == case {
+            CaseMode::Sensitive
+        } else {
+            case
+        };
+    }
+
+    /// The line number gutter for `page`, `None` when line numbers are
+    /// turned off.
+    pub fn gutter(&mut self, page: &(u64, Vec<u8>)) -> Option<Gutter> {
+        if !self.reader.line_numbers {
+            return None;
+        }
+
+        let offset = page.0;
+        let top_line = self.reader.line_of_offset(offset);
+        let continued = match top_line {
+            Some(line) => self.reader.offset_of_line(line) != Some(offset),
+            None => false,
+        };
+
+        // Filtered lines aren't numbered one after the other, so look up
+        // every line starting on the page.
+        let lines = if self.reader.is_filtered() {
+            let first = if continued { None } else { Some(offset) };
+            let shown = self.page_bounds.1.saturating_sub(offset) as usize;
+            let starts = page.1[..std::cmp::min(shown, page.1.len())]
+                .iter()
+                .enumerate()
+                .filter(|&(_, &b)| b == b'\n')
+                .map(|(i, _)| offset + i as u64 + 1);
+            let lines = first
+                .into_iter()
+                .chain(starts)
+                .map(|start| self.reader.line_of_offset(start))
+                .collect();
+            Some(lines)
+        } else {
+            None
+        };
+
+        Some(Gutter {
+            top_line,
+            continued,
+            lines,
+        })
+    }
+
+    /// The matches on the current page, at their offsets in what is
+    /// shown.
+    pub fn page_matches(&self) -> Vec<(u64, Match)> {
+        self.visible_matches(&self.matches)
+    }
+
+    /// The color and matches of every pinned pattern on the current page,
+    /// like `page_matches`.
+    pub fn page_highlights(&self) -> Vec<(u8, Vec<(u64, Match)>)> {
+        self.highlights
+            .iter()
+            .map(|h| (h.color, self.visible_matches(&h.matches)))
+            .collect()
+    }
+
+    fn visible_matches(&self, matches: &[(u64, Match)]) -> Vec<(u64, Match)> {
+        let (start, end) = self.page_bounds;
+        let from = self.reader.original_offset(start);
+        let first = matches
+            .partition_point(|(offset, mat)| offset + mat.end() as u64 <= from);
+
+        let reader = &self.reader;
+        matches[first..]
+            .iter()
+            .filter_map(|(offset, mat)| {
+                reader.view_offset(*offset).map(|offset| (offset, *mat))
+            })
+            .take_while(|(offset, _)| *offset < end)
+            .collect()
+    }
+
+    /// Pins `pattern`, it's highlighted in the next color of the palette.
+    fn add_highlight(
+        &mut self,
+        pattern: &str,
+        color: Option<String>,
+        options: SearchOptions,
+    ) -> Result<()> {
+        let color = match color {
+            Some(name) => match highlight::color_by_name(&name) {
+                Some(color) => color,
+                None => {
+                    let text = format!("Unknown color {}", name);
+                    self.messages.push(MessageKind::Error, text);
+                    return Ok(());
+                }
+            },
+            None => highlight::next_color(&self.highlights),
+        };
+        let mut highlight = Highlight::new(pattern, color, options)?;
+        highlight.start(&self.reader);
+        self.highlights.push(highlight);
+        Ok(())
+    }
+
+    /// Unpins highlight number `n`, counting from 1, or all of them.
+    fn remove_highlight(&mut self, n: Option<usize>) {
+        match n {
+            Some(n) if n >= 1 && n <= self.highlights.len() => {
+                self.highlights.remove(n - 1);
+            }
+            Some(n) => {
+                let text = format!("No highlight {}", n);
+                self.messages.push(MessageKind::Error, text)
+            }
+            None => self.highlights.clear(),
+        }
+    }
+
+    /// Shows only the lines matching `pattern`, or those that don't with
+    /// `invert`. An empty pattern shows every line again, keeping the same
+    /// line at the top of the screen.
+    fn set_filter(
+        &mut self,
+        pattern: &str,
+        invert: bool,
+        options: SearchOptions,
+    ) -> Result<()> {
+        let filter = if pattern.is_empty() {
+            None
+        } else {
+            Some((options.matcher(pattern)?, invert))
+        };
+
+        let offset = self.reader.current_offset();
+        let offset = self.reader.original_offset(offset);
+        self.search_origin = None;
+        self.reader.filter(filter);
+        self.filter_anchor = Some(offset);
+        if !self.jump_to_filter_anchor()? {
+            self.reader.jump_offset(0)?;
+        }
+        Ok(())
+    }
+
+    /// Puts `line` at the top of the screen, or has `poll` do it once the
+    /// line has been indexed.
+    fn jump_line(&mut self, line: u64) -> Result<()> {
+        if !self.reader.jump_line(line)? {
+            self.pending_line = Some(line);
+        }
+        Ok(())
+    }
+
+    /// Makes a jump to a line that was waiting for it to be indexed.
+    /// Returns true if it was made.
+    fn poll_line_jump(&mut self) -> Result<bool> {
+        match self.pending_line.take() {
+            Some(line) => {
+                self.jump_line(line)?;
+                Ok(self.pending_line.is_none())
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Moves to where the view was when the filter was set, once the
+    /// filter knows where that is. Returns true if it moved.
+    fn jump_to_filter_anchor(&mut self) -> Result<bool> {
+        let offset = match self.filter_anchor {
+            Some(anchor) => match self.reader.view_offset_after(anchor) {
+                Some(offset) => offset,
+                None => return Ok(false),
+            },
+            None => return Ok(false),
+        };
+        self.filter_anchor = None;
+        self.reader.jump_offset(offset)?;
+        // The anchor may be in the middle of a line that is shown.
+        self.reader.jump_line_start()?;
+        Ok(true)
+    }
+
+    /// Starts a search for `pattern` in the background, the matches are
+    /// collected by `poll`. Nothing changes if `pattern` isn't valid.
+    fn find_matches(
+        &mut self,
+        pattern: &str,
+        options: SearchOptions,
+    ) -> std::result::Result<(), regex::Error> {
+        let matcher = options.matcher(pattern)?;
         self.matches.clear();
-        self.reader.search(&mut self.matches, pattern);
+        self.current_match = None;
+        self.search_error = None;
+        // Other files are searched again when they are switched to.
+        self.forget_file_matches();
+        self.search = self.reader.search(matcher.clone(), 0);
+        self.resume_point = None;
+        self.matcher = Some(matcher);
+        Ok(())
+    }
+
+    /// Collects the matches found since the last call, and makes a jump
+    /// that was waiting for them. Returns true if anything changed.
+    fn poll_search(&mut self) -> bool {
+        let (changed, done) = match &mut self.search {
+            Some(search) => {
+                (search.receive(&mut self.matches), search.is_done())
+            }
+            None => return false,
+        };
+        if done {
+            self.resume_point = self.search.take().map(|s| s.resume_point());
+        }
+
+        // The jump may go on into another file and wait there instead.
+        if let Some(jump) = self.pending_jump.take() {
+            self.start_jump(jump);
+        }
+        changed
     }
 
-    fn jump_next_match(&mut self, is_forward: bool) {
+    /// Stops the search, keeping the matches found so far.
+    fn stop_search(&mut self) {
+        if let Some(search) = &mut self.search {
+            search.cancel();
+            search.receive(&mut self.matches);
+        }
+        self.search = None;
+        self.pending_jump = None;
+    }
+
+    /// Highlights the matches of a pattern that is still being typed, and
+    /// moves to the next one from where the search started.
+    fn preview_search(
+        &mut self,
+        pattern: &str,
+        is_forward: bool,
+        options: SearchOptions,
+    ) -> Result<()> {
+        if self.search_origin.is_none() {
+            self.search_origin = Some(SearchOrigin {
+                offset: self.reader.current_offset(),
+                h_offset: self.h_offset,
+                matches: self.matches.clone(),
+                matcher: self.matcher.clone(),
+                resume_point: self.resume_point,
+            });
+        }
+        self.return_to_search_origin()?;
+
+        if pattern.is_empty() {
+            self.matches.clear();
+            self.current_match = None;
+            self.search_error = None;
+        } else {
+            match self.find_matches(pattern, options) {
+                Ok(()) => self.jump_next_match(is_forward, false),
+                Err(e) => {
+                    self.search = None;
+                    self.matches.clear();
+                    self.current_match = None;
+                    self.search_error = Some(error::regex_message(&e));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Goes back to where the view was before the search prompt, with the
+    /// highlights of the previous search.
+    fn cancel_search(&mut self) -> Result<()> {
+        self.search = None;
+        self.return_to_search_origin()?;
+        if let Some(origin) = self.search_origin.take() {
+            self.matches = origin.matches;
+            self.matcher = origin.matcher;
+            self.resume_point = origin.resume_point;
+            self.current_match = None;
+            self.forget_file_matches();
+        }
+        self.search_error = None;
+        Ok(())
+    }
+
+    fn return_to_search_origin(&mut self) -> Result<()> {
+        if let Some(origin) = &self.search_origin {
+            self.reader.jump_offset(origin.offset)?;
+            self.h_offset = origin.h_offset;
+        }
+        Ok(())
+    }
+
+    /// Jumps to the next match, or waits for the search to find it.
+    fn jump_next_match(&mut self, is_forward: bool, across_files: bool) {
         let cur_offset = self.reader.current_offset();
-        if is_forward {
-            self.jump_forward_match(cur_offset)
+        let cur_offset = self.reader.original_offset(cur_offset);
+        let after = self.current_match.filter(|&i| {
+            self.matches.get(i).map(|(offset, _)| *offset) == Some(cur_offset)
+        });
+        let jump = MatchJump {
+            from: Some(cur_offset),
+            after,
+            is_forward,
+            across_files,
+            start_file: self.reader.file_index(),
+        };
+        self.start_jump(jump);
+    }
+
+    fn start_jump(&mut self, jump: MatchJump) {
+        if !self.jump_match(jump) {
+            self.pending_jump = Some(jump);
+        }
+    }
+
+    /// Returns false if it isn't known yet where to jump, because the
+    /// search hasn't got that far.
+    fn jump_match(&mut self, jump: MatchJump) -> bool {
+        if jump.is_forward {
+            self.jump_forward_match(jump)
         } else {
-            self.jump_backward_match(cur_offset)
+            self.jump_backward_match(jump)
+        }
+    }
+
+    /// Offsets are in the input, matches in lines that are filtered out
+    /// are skipped.
+    fn jump_forward_match(&mut self, jump: MatchJump) -> bool {
+        let next = match jump.after {
+            Some(i) => {
+                self.visible_match(self.matches[i + 1..].iter(), |_| true)
+            }
+            None => self.visible_match(self.matches.iter(), |offset| {
+                jump.from.is_none_or(|from| offset > from)
+            }),
         };
+        match next {
+            Some(m) => self.jump_to_match(m),
+            None if self.search.is_some() => return false,
+            None => self.no_more_matches(jump),
+        }
+        true
     }
 
-    fn jump_forward_match(&mut self, cur_offset: u64) {
-        match self.matches.iter().find(|(offset, _)| *offset > cur_offset) {
-            Some((offset, _)) => self.reader.jump_offset(*offset).unwrap(),
-            None => (),
+    fn jump_backward_match(&mut self, jump: MatchJump) -> bool {
+        // Matches arrive in order, so the closest one before `from` is
+        // only known once the search has passed it. Those before `after`
+        // are all known.
+        let passed = match (self.matches.last(), jump.from) {
+            (Some((offset, _)), Some(from)) => *offset >= from,
+            _ => false,
+        };
+        if self.search.is_some() && jump.after.is_none() && !passed {
+            return false;
         }
+
+        let previous = match jump.after {
+            Some(i) => {
+                self.visible_match(self.matches[..i].iter().rev(), |_| true)
+            }
+            None => self.visible_match(self.matches.iter().rev(), |offset| {
+                jump.from.is_none_or(|from| offset < from)
+            }),
+        };
+        match previous {
+            Some(m) => self.jump_to_match(m),
+            // Wrapping needs the last match, so wait for the search.
+            None if self.search.is_some() => return false,
+            None => self.no_more_matches(jump),
+        }
+        true
     }
 
-    fn jump_backward_match(&mut self, cur_offset: u64) {
-        match self
-            .matches
-            .iter()
-            .rev()
-            .find(|(offset, _)| *offset < cur_offset)
-        {
-            Some((offset, _)) => self.reader.jump_offset(*offset).unwrap(),
-            None => (),
+    /// Goes on into the next or previous file for a jump across files,
+    /// or else wraps around in this one.
+    fn no_more_matches(&mut self, jump: MatchJump) {
+        if !jump.across_files {
+            return self.wrap_around(jump.is_forward);
         }
+
+        let index = self.reader.file_index();
+        let next = if jump.is_forward {
+            Some(index + 1)
+        } else {
+            index.checked_sub(1)
+        };
+        let switched = match next {
+            Some(next) => self.switch_file(next).is_ok(),
+            None => false,
+        };
+        if switched {
+            self.start_jump(MatchJump {
+                from: None,
+                after: None,
+                ..jump
+            });
+        } else {
+            if index != jump.start_file {
+                let _ = self.switch_file(jump.start_file);
+            }
+            let text = String::from("No more matches");
+            self.messages.push(MessageKind::Info, text);
+        }
+    }
+
+    /// The first of `matches` at an offset accepted by `wanted`, and not in
+    /// a line that is filtered out.
+    fn visible_match<'a, I, F>(
+        &self,
+        mut matches: I,
+        wanted: F,
+    ) -> Option<(u64, Match)>
+    where
+        I: Iterator<Item = &'a (u64, Match)>,
+        F: Fn(u64) -> bool,
+    {
+        matches
+            .find(|(offset, _)| {
+                wanted(*offset) && self.reader.view_offset(*offset).is_some()
+            })
+            .cloned()
     }
 
-    fn next_file(&mut self) {
-        match self.reader.next_file() {
-            Err(e) => eprintln!("{}", e),
-            Ok(_) => {
-                self.matches.clear();
-                ()
+    /// Goes on from the first match, or the last, when there are no more
+    /// in the direction of the search. Only when `wrap_search` is set.
+    fn wrap_around(&mut self, is_forward: bool) {
+        let first = if is_forward {
+            self.visible_match(self.matches.iter(), |_| true)
+        } else {
+            self.visible_match(self.matches.iter().rev(), |_| true)
+        };
+        match first {
+            None => {
+                let text = String::from("Pattern not found");
+                self.messages.push(MessageKind::Error, text)
+            }
+            Some(m) if self.wrap_search => {
+                self.jump_to_match(m);
+                self.search_wrapped = true;
+            }
+            Some(_) => {
+                let text = String::from("No more matches");
+                self.messages.push(MessageKind::Info, text)
             }
         }
     }
+
+    /// Moves to the line of `m`, which is in the view.
+    fn jump_to_match(&mut self, m: (u64, Match)) {
+        let (offset, mat) = m;
+        if let Some(view_offset) = self.reader.view_offset(offset) {
+            self.reader.jump_offset(view_offset).unwrap();
+            self.scroll_to_match(&mat);
+        }
+        self.current_match =
+            Some(self.matches.partition_point(|(o, m)| {
+                (*o, m.start()) < (offset, mat.start())
+            }));
+    }
+
+    /// Scrolls horizontally so that `mat`, in the line at the top of the
+    /// screen, is visible when lines aren't wrapped.
+    fn scroll_to_match(&mut self, mat: &Match) {
+        if self.reader.wrap {
+            return;
+        }
+
+        // The top of the screen is at the start of the line.
+        let column = match self.reader.peek(mat.start()) {
+            Ok(before) => match str::from_utf8(&before) {
+                Ok(before) if self.reader.ansi => ansi::width(before),
+                Ok(before) => util::grapheme_count(before),
+                Err(_) => return,
+            },
+            Err(_) => return,
+        };
+
+        let (screen_width, _) = util::screen_width_height();
+        let text_width =
+            util::text_width(screen_width, self.reader.line_numbers) as usize;
+        // The last column is taken by the indicator for cut off lines.
+        if column < self.h_offset || column + 1 >= self.h_offset + text_width {
+            self.h_offset = column.saturating_sub(text_width / 2);
+        }
+    }
+
+    fn next_file(&mut self) -> Result<()> {
+        let left = self.file_position();
+        self.reader.next_file()?;
+        self.file_switched(left)
+    }
+
+    fn switch_file(&mut self, index: usize) -> Result<()> {
+        let left = self.file_position();
+        self.reader.switch_file(index)?;
+        self.file_switched(left)
+    }
+
+    fn open_file(&mut self, path: &str) -> Result<()> {
+        let left = self.file_position();
+        match self.reader.open_file(path) {
+            Ok(_) => self.file_switched(left),
+            Err(e) => {
+                self.show_error(&e);
+                Ok(())
+            }
+        }
+    }
+
+    fn close_file(&mut self) -> Result<()> {
+        let closed = self.reader.file_index();
+        if let Err(e) = self.reader.close_file() {
+            self.show_error(&e);
+            return Ok(());
+        }
+
+        // The files after the closed one have moved up.
+        self.file_views = mem::take(&mut self.file_views)
+            .into_iter()
+            .filter(|(index, _)| *index != closed)
+            .map(|(index, view)| {
+                if index > closed {
+                    (index - 1, view)
+                } else {
+                    (index, view)
+                }
+            })
+            .collect();
+        self.matches.clear();
+        self.search = None;
+        self.resume_point = None;
+        self.file_entered()
+    }
+
+    /// The current file, and the offset in the input at the top of the
+    /// screen, to keep when switching to another one.
+    fn file_position(&mut self) -> (usize, u64) {
+        let offset = self.reader.current_offset();
+        (
+            self.reader.file_index(),
+            self.reader.original_offset(offset),
+        )
+    }
+
+    /// Keeps the view of the file that was left at `offset`, and brings
+    /// back that of the new one.
+    fn file_switched(&mut self, (left, offset): (usize, u64)) -> Result<()> {
+        let view = FileView {
+            offset,
+            h_offset: self.h_offset,
+            wrap: self.reader.wrap,
+            matches: Some(FileMatches {
+                matches: mem::take(&mut self.matches),
+                search: self.search.take(),
+                resume_point: self.resume_point.take(),
+                current_match: self.current_match,
+            }),
+        };
+        self.file_views.insert(left, view);
+        self.file_entered()
+    }
+
+    /// Goes back to where the current file was left, or to its start if
+    /// it wasn't shown before, and searches it if its matches aren't kept.
+    fn file_entered(&mut self) -> Result<()> {
+        self.pending_jump = None;
+        self.filter_anchor = None;
+        self.current_match = None;
+        self.search = None;
+        self.resume_point = None;
+        // Not shown yet, and never for a file that couldn't be opened.
+        self.page_bounds = (0, 0);
+
+        let view = self.file_views.remove(&self.reader.file_index());
+        let (offset, kept) = match view {
+            Some(view) => {
+                self.h_offset = view.h_offset;
+                self.reader.wrap = view.wrap;
+                (view.offset, view.matches)
+            }
+            None => {
+                self.h_offset = 0;
+                self.reader.wrap = self.default_wrap;
+                (0, None)
+            }
+        };
+        self.reader.jump_offset(offset)?;
+
+        match kept {
+            Some(kept) => {
+                self.matches = kept.matches;
+                self.search = kept.search;
+                self.resume_point = kept.resume_point;
+                self.current_match = kept.current_match;
+            }
+            None => {
+                if let Some(matcher) = &self.matcher {
+                    self.search = self.reader.search(matcher.clone(), 0);
+                }
+            }
+        }
+
+        for highlight in &mut self.highlights {
+            highlight.start(&self.reader);
+        }
+        Ok(())
+    }
+
+    /// Drops the matches kept for other files, they are searched again
+    /// when switched to.
+    fn forget_file_matches(&mut self) {
+        for view in self.file_views.values_mut() {
+            view.matches = None;
+        }
+    }
+}
+
+/// The files in `names`, numbered, with `current` marked.
+fn file_list(names: &[String], current: usize) -> String {
+    let lines: Vec<String> = names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let name = if name.is_empty() {
+                "(standard input)"
+            } else {
+                name
+            };
+            let mark = if i == current { '*' } else { ' ' };
+            format!("{} {:>3} {}", mark, i + 1, name)
+        })
+        .collect();
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::File;
+    use std::os::unix::io::FromRawFd;
+
+    use app::{self, InputFile, InputType};
+    use reader::StdinCursor;
+
+    /// A controller reading from a pipe, and the end to write to it.
+    fn piped_controller() -> (Controller, File) {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let (input, writer) =
+            unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+        let reader =
+            InputReader::new(InputType::Stdin(StdinCursor::new(input)));
+        (Controller::new(reader, true), writer)
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let (mut controller, _writer) = piped_controller();
+        let options = SearchOptions::default();
+        let highlight = Command::Highlight(String::from("a"), None, options);
+        controller.update_command(highlight).unwrap();
+        assert!(!controller.check_interrupt());
+
+        let search = Command::Search(String::from("b"), true, options);
+        controller.update_command(search).unwrap();
+        assert!(controller.pending_jump.is_some());
+        interrupt::interrupt();
+        assert!(controller.check_interrupt());
+        assert!(!interrupt::take());
+        assert!(controller.search.is_none());
+        assert!(controller.pending_jump.is_none());
+
+        controller.update_command(Command::JumpLine(5)).unwrap();
+        assert_eq!(controller.status().indexing, Some(5));
+        interrupt::interrupt();
+        assert!(controller.check_interrupt());
+        assert_eq!(controller.status().indexing, None);
+
+        // Nothing was waiting for the highlight.
+        assert!(controller.highlights[0].is_searching());
+    }
+
+    /// A controller viewing a file for each of `texts`.
+    fn file_controller(texts: &[&str]) -> Controller {
+        let files = texts
+            .iter()
+            .enumerate()
+            .map(|(i, text)| {
+                let name = format!("{}.txt", i + 1);
+                let path = ::std::env::temp_dir().join(format!(
+                    "less2-controller-{}-{:?}-{}",
+                    ::std::process::id(),
+                    ::std::thread::current().id(),
+                    name
+                ));
+                ::std::fs::write(&path, text).unwrap();
+                let file = app::open(path.to_str().unwrap());
+                ::std::fs::remove_file(&path).unwrap();
+                InputFile { name, file }
+            })
+            .collect();
+        Controller::new(InputReader::new(InputType::Files(files)), true)
+    }
+
+    fn wait_for_search(controller: &mut Controller) {
+        while controller.search.is_some() {
+            controller.poll().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_match_steps() {
+        let mut controller = file_controller(&["a a\nb\na\n"]);
+        let options = SearchOptions::default();
+        let search = Command::Search(String::from("a"), true, options);
+        controller.update_command(search).unwrap();
+        wait_for_search(&mut controller);
+        // The search starts after the line at the top of the screen.
+        let status = controller.status();
+        assert_eq!(
+            (status.current_match, status.match_count),
+            (Some(3), Some(3))
+        );
+        assert_eq!(controller.reader.current_offset(), 6);
+
+        // Every match in a line is stepped through.
+        let next = || Command::JumpNextMatch(true);
+        let previous = || Command::JumpNextMatch(false);
+        controller.update_command(previous()).unwrap();
+        assert_eq!(controller.status().current_match, Some(2));
+        assert_eq!(controller.reader.current_offset(), 0);
+        controller.update_command(previous()).unwrap();
+        assert_eq!(controller.status().current_match, Some(1));
+        controller.update_command(next()).unwrap();
+        assert_eq!(controller.status().current_match, Some(2));
+        controller.update_command(next()).unwrap();
+        assert_eq!(controller.status().current_match, Some(3));
+        assert_eq!(controller.reader.current_offset(), 6);
+    }
+
+    /// The file shown, the offset at the top of the screen, the columns
+    /// scrolled and whether lines are wrapped.
+    fn view(controller: &mut Controller) -> (usize, u64, usize, bool) {
+        (
+            controller.reader.file_index(),
+            controller.reader.current_offset(),
+            controller.h_offset,
+            controller.reader.wrap,
+        )
+    }
+
+    #[test]
+    fn test_file_views() {
+        let mut controller =
+            file_controller(&["a\nb\na\nc\n", "b\n", "a\nc\n"]);
+        let options = SearchOptions::default();
+        let search = Command::Search(String::from("a"), true, options);
+        controller.update_command(search).unwrap();
+        wait_for_search(&mut controller);
+        controller.reader.wrap = false;
+        controller.update_command(Command::ScrollRight(3)).unwrap();
+        let matches = controller.matches.clone();
+        assert_eq!(view(&mut controller), (0, 4, 3, false));
+
+        // A file not shown before starts at the top, as lines were wrapped
+        // on the command line.
+        controller.update_command(Command::NextFile).unwrap();
+        assert_eq!(view(&mut controller), (1, 0, 0, true));
+        controller.update_command(Command::PreviousFile).unwrap();
+        assert_eq!(view(&mut controller), (0, 4, 3, false));
+        assert_eq!(controller.matches, matches);
+        assert_eq!(controller.status().current_match, Some(2));
+
+        controller.update_command(Command::JumpFile(3)).unwrap();
+        controller.reader.jump_offset(2).unwrap();
+        controller.reader.wrap = false;
+        controller.update_command(Command::ScrollRight(5)).unwrap();
+        controller.update_command(Command::JumpFile(2)).unwrap();
+
+        // The views of the files after the one closed move up with them.
+        controller.update_command(Command::CloseFile).unwrap();
+        assert_eq!(view(&mut controller), (1, 2, 5, false));
+        controller.update_command(Command::PreviousFile).unwrap();
+        assert_eq!(view(&mut controller), (0, 4, 3, false));
+        assert_eq!(controller.matches, matches);
+    }
+
+    #[test]
+    fn test_file_list() {
+        let names = vec![String::from("a.txt"), String::from("b.txt")];
+        assert_eq!(file_list(&names, 1), "    1 a.txt\n*   2 b.txt");
+        assert_eq!(file_list(&[String::new()], 0), "*   1 (standard input)");
+    }
 }
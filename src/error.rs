@@ -1,36 +1,136 @@
 use std::convert::From;
 use std::error;
 use std::fmt;
+use std::io;
+
+use grep::regex;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub enum Error {
-    Other,
-    IOError,
+    /// Reading from or seeking in the input failed.
+    Io(io::Error),
+    /// The file at `path` couldn't be opened.
+    Open {
+        path: String,
+        error: io::Error,
+    },
+    /// A pattern isn't a valid regex.
+    Regex(regex::Error),
     NoNextFile,
-    SearchError,
+    /// There is nothing more below the current position.
+    EndOfInput,
+    /// Something that can't be done, saying why, e.g. closing the last
+    /// file.
+    Unsupported(&'static str),
+    /// `error` happened while trying to `operation`, e.g. "follow the
+    /// input".
+    Context {
+        operation: &'static str,
+        error: Box<Error>,
+    },
+}
+
+impl Error {
+    /// Says what was being done when the error happened.
+    pub fn context(self, operation: &'static str) -> Error {
+        Error::Context {
+            operation,
+            error: Box::new(self),
+        }
+    }
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "somethin went wrong!")
+        match self {
+            Error::Io(error) => write!(f, "{}", io_message(error)),
+            Error::Open { path, error } => {
+                write!(f, "{}: {}", path, io_message(error))
+            }
+            Error::Regex(error) => {
+                write!(f, "Invalid pattern: {}", regex_message(error))
+            }
+            Error::NoNextFile => write!(f, "No next file"),
+            Error::EndOfInput => write!(f, "End of input"),
+            Error::Unsupported(reason) => write!(f, "{}", reason),
+            Error::Context { operation, error } => {
+                write!(f, "Can't {}: {}", operation, error)
+            }
+        }
     }
 }
 
 impl error::Error for Error {
-    fn description(&self) -> &str {
-        "somethin went wrong!"
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            Error::Open { error, .. } => Some(error),
+            Error::Regex(error) => Some(error),
+            Error::Context { error, .. } => Some(&**error),
+            _ => None,
+        }
     }
+}
 
-    fn cause(&self) -> Option<&error::Error> {
-        // Generic error, underlying cause isn't tracked.
-        None
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
     }
 }
 
-impl From<std::io::Error> for Error {
-    fn from(_err: std::io::Error) -> Error {
-        Error::IOError
+impl From<regex::Error> for Error {
+    fn from(err: regex::Error) -> Error {
+        Error::Regex(err)
+    }
+}
+
+/// What went wrong, without the number of the OS error, e.g. "No such file
+/// or directory".
+fn io_message(error: &io::Error) -> String {
+    let message = error.to_string();
+    match message.find(" (os error ") {
+        Some(i) => message[..i].to_string(),
+        None => message,
+    }
+}
+
+/// The last line of a regex error, which says what is wrong without the
+/// lines pointing out where.
+pub fn regex_message(error: &regex::Error) -> String {
+    let error = error.to_string();
+    let line = error.lines().rev().find(|l| !l.trim().is_empty());
+    let line = line.unwrap_or(&error).trim();
+    line.trim_start_matches("error: ").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use grep::regex::RegexMatcher;
+
+    #[test]
+    fn test_display() {
+        let error = RegexMatcher::new("a(b").unwrap_err();
+        assert_eq!(regex_message(&error), "unclosed group");
+        assert_eq!(
+            Error::from(error).to_string(),
+            "Invalid pattern: unclosed group"
+        );
+
+        let error = io::Error::from_raw_os_error(13);
+        let error = Error::Open {
+            path: String::from("a.txt"),
+            error,
+        };
+        assert_eq!(error.to_string(), "a.txt: Permission denied");
+
+        let error = Error::Io(io::Error::from_raw_os_error(5));
+        assert_eq!(
+            error.context("follow the input").to_string(),
+            "Can't follow the input: Input/output error"
+        );
     }
 }
@@ -0,0 +1,337 @@
+use std::cmp;
+use std::io::{self, SeekFrom};
+use std::sync::Arc;
+
+use grep::matcher::Match;
+use grep::regex::RegexMatcher;
+
+use line_index::ReadAt;
+use searcher::BackgroundSearch;
+
+/// A line of the input that is part of the filtered view.
+#[derive(Debug, Clone, Copy)]
+struct Line {
+    /// Offset in the input.
+    start: u64,
+    len: u64,
+    /// Offset in the filtered view.
+    view_start: u64,
+}
+
+/// A view of the input with only the lines that match a pattern, or only
+/// those that don't. The view reads like the lines were all there is, and
+/// grows as a background search finds more of them, also in what is
+/// appended to an input that may still grow.
+pub struct Filter {
+    source: Arc<dyn ReadAt + Send + Sync>,
+    matcher: RegexMatcher,
+    invert: bool,
+    search: Option<BackgroundSearch>,
+    /// Where to go on looking for lines from when the input is followed,
+    /// once the search is done.
+    resume_point: Option<u64>,
+    lines: Vec<Line>,
+    /// Position in the view.
+    pos: u64,
+}
+
+impl Filter {
+    pub fn new(
+        matcher: RegexMatcher,
+        source: Arc<dyn ReadAt + Send + Sync>,
+        invert: bool,
+    ) -> Filter {
+        let search =
+            BackgroundSearch::lines(matcher.clone(), source.clone(), 0, invert);
+        Filter {
+            source,
+            matcher,
+            invert,
+            search: Some(search),
+            resume_point: None,
+            lines: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    /// Adds the lines found since the last call.
+    pub fn update(&mut self) {
+        let mut found: Vec<(u64, Match)> = Vec::new();
+        let done = match &mut self.search {
+            Some(search) => {
+                search.receive(&mut found);
+                search.is_done()
+            }
+            None => return,
+        };
+        if done {
+            self.resume_point = self.search.take().map(|s| s.resume_point());
+        }
+
+        for (start, line) in found {
+            let view_start = self.len();
+            self.lines.push(Line {
+                start: start + line.start() as u64,
+                len: line.len() as u64,
+                view_start,
+            });
+        }
+    }
+
+    /// Looks for more lines in what has been appended to the input since
+    /// the search was done.
+    pub fn resume(&mut self) {
+        let from = match self.resume_point.take() {
+            Some(from) => from,
+            None => return,
+        };
+        // The last line may have been incomplete, it's found again.
+        while self.lines.last().is_some_and(|line| line.start >= from) {
+            self.lines.pop();
+        }
+        self.search = Some(BackgroundSearch::lines(
+            self.matcher.clone(),
+            self.source.clone(),
+            from,
+            self.invert,
+        ));
+    }
+
+    /// True once every line in the view has been found.
+    pub fn is_done(&self) -> bool {
+        self.search.is_none()
+    }
+
+    /// The size of the view so far.
+    pub fn len(&self) -> u64 {
+        match self.lines.last() {
+            Some(line) => line.view_start + line.len,
+            None => 0,
+        }
+    }
+
+    pub fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.update();
+
+        let mut read = 0;
+        while read < buf.len() {
+            let line = match self.line_at(self.pos) {
+                Some(line) => line,
+                None => break,
+            };
+            let into_line = self.pos - line.view_start;
+            let want =
+                cmp::min((line.len - into_line) as usize, buf.len() - read);
+            let n = self
+                .source
+                .read_at(&mut buf[read..read + want], line.start + into_line)?;
+            if n == 0 {
+                break;
+            }
+            read += n;
+            self.pos += n as u64;
+        }
+        Ok(read)
+    }
+
+    pub fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.update();
+
+        let pos = match pos {
+            SeekFrom::Start(offset) => offset as i64,
+            SeekFrom::Current(offset) => self.pos as i64 + offset,
+            SeekFrom::End(offset) => self.len() as i64 + offset,
+        };
+        if pos < 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Seek before the start of the filtered view.",
+            ));
+        }
+        self.pos = pos as u64;
+        Ok(self.pos)
+    }
+
+    /// The offset in the input of `offset` in the view.
+    pub fn original_offset(&self, offset: u64) -> u64 {
+        match self.line_at(offset) {
+            Some(line) => line.start + offset - line.view_start,
+            // Past the end of the view, i.e. after its last line.
+            None => match self.lines.last() {
+                Some(line) => line.start + line.len,
+                None => 0,
+            },
+        }
+    }
+
+    /// The offset in the view of `offset` in the input, `None` if it's in
+    /// a line that isn't shown.
+    pub fn view_offset(&self, offset: u64) -> Option<u64> {
+        let i = self.line_index_after(offset);
+        let line = self.lines.get(i)?;
+        if line.start <= offset {
+            Some(line.view_start + offset - line.start)
+        } else {
+            None
+        }
+    }
+
+    /// The offset in the view of `offset` in the input, or of the first
+    /// shown line after it. `None` until the search has got that far.
+    pub fn view_offset_after(&self, offset: u64) -> Option<u64> {
+        let i = self.line_index_after(offset);
+        match self.lines.get(i) {
+            Some(line) if line.start <= offset => {
+                Some(line.view_start + offset - line.start)
+            }
+            Some(line) => Some(line.view_start),
+            None if self.is_done() => Some(self.len()),
+            None => None,
+        }
+    }
+
+    /// The line containing `offset` in the view.
+    fn line_at(&self, offset: u64) -> Option<Line> {
+        let i = match self
+            .lines
+            .binary_search_by(|line| line.view_start.cmp(&offset))
+        {
+            Ok(i) => i,
+            Err(0) => return None,
+            Err(i) => i - 1,
+        };
+        let line = self.lines[i];
+        if offset < line.view_start + line.len {
+            Some(line)
+        } else {
+            None
+        }
+    }
+
+    /// The index of the first line that ends after `offset` in the input.
+    fn line_index_after(&self, offset: u64) -> usize {
+        match self
+            .lines
+            .binary_search_by(|line| (line.start + line.len).cmp(&offset))
+        {
+            Ok(i) => i + 1,
+            Err(i) => i,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::{self, File, OpenOptions};
+    use std::io::Write;
+    use std::thread;
+    use std::time::Duration;
+
+    use reader::StdinBuffer;
+
+    fn filter(pattern: &str, invert: bool) -> Filter {
+        let file = File::open("tests/resources/bible_short.txt");
+        let matcher = RegexMatcher::new(pattern).unwrap();
+        let mut filter = Filter::new(matcher, Arc::new(file.unwrap()), invert);
+        wait_until_done(&mut filter);
+        filter
+    }
+
+    fn wait_until_done(filter: &mut Filter) {
+        while !filter.is_done() {
+            filter.update();
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn wait_for_len(filter: &mut Filter, len: u64) {
+        while filter.len() < len {
+            filter.update();
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Reads the whole view, which is small enough to fit in one read.
+    fn read_all(filter: &mut Filter) -> String {
+        let mut buf = vec![0; 4096];
+        filter.seek(SeekFrom::Start(0)).unwrap();
+        let n = filter.read(&mut buf).unwrap();
+        String::from_utf8(buf[..n].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_view() {
+        let text = ::std::fs::read_to_string("tests/resources/bible_short.txt")
+            .unwrap();
+        let matching: String = text
+            .split_inclusive('\n')
+            .filter(|line| line.contains("God"))
+            .collect();
+        let other: String = text
+            .split_inclusive('\n')
+            .filter(|line| !line.contains("God"))
+            .collect();
+
+        assert_eq!(read_all(&mut filter("God", false)), matching);
+        assert_eq!(read_all(&mut filter("God", true)), other);
+    }
+
+    #[test]
+    fn test_growing_input() {
+        let buffer = Arc::new(StdinBuffer::new());
+        buffer.append(b"a ERROR\nb\n");
+        let matcher = RegexMatcher::new("ERROR").unwrap();
+        let mut filter = Filter::new(matcher, buffer.clone(), false);
+        wait_for_len(&mut filter, 8);
+
+        buffer.append(b"c ERROR\nd\n");
+        wait_for_len(&mut filter, 16);
+        assert!(!filter.is_done());
+        assert_eq!(read_all(&mut filter), "a ERROR\nc ERROR\n");
+
+        buffer.close();
+        wait_until_done(&mut filter);
+        assert_eq!(filter.len(), 16);
+    }
+
+    #[test]
+    fn test_resume() {
+        let path = ::std::env::temp_dir()
+            .join(format!("less2-filter-{}.txt", ::std::process::id()));
+        fs::write(&path, "a ERROR\nb\nc ERR").unwrap();
+        let matcher = RegexMatcher::new("ERROR").unwrap();
+        let source = Arc::new(File::open(&path).unwrap());
+        let mut filter = Filter::new(matcher, source, false);
+        wait_until_done(&mut filter);
+        assert_eq!(read_all(&mut filter), "a ERROR\n");
+
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"OR\nd ERROR\n").unwrap();
+        filter.resume();
+        wait_until_done(&mut filter);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(read_all(&mut filter), "a ERROR\nc ERROR\nd ERROR\n");
+    }
+
+    #[test]
+    fn test_offsets() {
+        let text = ::std::fs::read_to_string("tests/resources/bible_short.txt")
+            .unwrap();
+        let mut filter = filter("God", false);
+        let view = read_all(&mut filter);
+
+        let second = view.find('\n').unwrap() as u64 + 1;
+        let original = filter.original_offset(second);
+        assert_eq!(
+            &text[original as usize..original as usize + 10],
+            &view[second as usize..second as usize + 10]
+        );
+        assert_eq!(filter.view_offset(original), Some(second));
+        assert_eq!(filter.view_offset_after(original + 1), Some(second + 1));
+        // Past the end is after the last line shown.
+        let last_end = text.rfind("waters.").unwrap() as u64;
+        assert_eq!(filter.original_offset(filter.len() + 5), last_end);
+    }
+}
@@ -0,0 +1,170 @@
+use grep::matcher::Match;
+use grep::regex::{self, RegexMatcher};
+
+use reader::Search;
+use searcher::{BackgroundSearch, SearchOptions};
+
+/// Background colors of pinned patterns, as ANSI color numbers, in the
+/// order they're given to patterns pinned without one.
+pub const PALETTE: [u8; 6] = [3, 6, 2, 5, 1, 4];
+
+/// The names of the colors in `PALETTE`, for `:hl -c`.
+const COLOR_NAMES: [(&str, u8); 6] = [
+    ("red", 1),
+    ("green", 2),
+    ("yellow", 3),
+    ("blue", 4),
+    ("magenta", 5),
+    ("cyan", 6),
+];
+
+/// A pattern that stays highlighted in its own color, whatever the search
+/// `n` and `N` move between is.
+pub struct Highlight {
+    pub pattern: String,
+    /// An ANSI color number, kept when other patterns are unpinned.
+    pub color: u8,
+    matcher: RegexMatcher,
+    search: Option<BackgroundSearch>,
+    /// Where to go on searching from when the input is followed, once the
+    /// search is done.
+    resume_point: Option<u64>,
+    pub matches: Vec<(u64, Match)>,
+}
+
+impl Highlight {
+    pub fn new(
+        pattern: &str,
+        color: u8,
+        options: SearchOptions,
+    ) -> Result<Highlight, regex::Error> {
+        Ok(Highlight {
+            pattern: pattern.to_string(),
+            color,
+            matcher: options.matcher(pattern)?,
+            search: None,
+            resume_point: None,
+            matches: Vec::new(),
+        })
+    }
+
+    /// Searches the current file of `reader` from the start, dropping the
+    /// matches of the last search.
+    pub fn start<S: Search>(&mut self, reader: &S) {
+        self.matches.clear();
+        self.resume_point = None;
+        self.search = reader.search(self.matcher.clone(), 0);
+    }
+
+    /// Goes on searching what has been appended to the current file of
+    /// `reader` since the search was done.
+    pub fn resume<S: Search>(&mut self, reader: &S) {
+        if let Some(from) = self.resume_point.take() {
+            self.matches.retain(|(offset, _)| *offset < from);
+            self.search = reader.search(self.matcher.clone(), from);
+        }
+    }
+
+    /// Collects the matches found since the last call. Returns true if
+    /// there were any.
+    pub fn poll(&mut self) -> bool {
+        let (changed, done) = match &mut self.search {
+            Some(search) => {
+                (search.receive(&mut self.matches), search.is_done())
+            }
+            None => return false,
+        };
+        if done {
+            self.resume_point = self.search.take().map(|s| s.resume_point());
+        }
+        changed
+    }
+
+    pub fn is_searching(&self) -> bool {
+        self.search.is_some()
+    }
+}
+
+/// The color named `name`, e.g. "red".
+pub fn color_by_name(name: &str) -> Option<u8> {
+    COLOR_NAMES
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|&(_, color)| color)
+}
+
+fn color_name(color: u8) -> &'static str {
+    COLOR_NAMES
+        .iter()
+        .find(|&&(_, c)| c == color)
+        .map_or("?", |&(name, _)| name)
+}
+
+/// The color for a pattern pinned without one: the first in `PALETTE`
+/// that no pinned pattern has, or else the next one round.
+pub fn next_color(highlights: &[Highlight]) -> u8 {
+    PALETTE
+        .iter()
+        .cloned()
+        .find(|&color| highlights.iter().all(|h| h.color != color))
+        .unwrap_or(PALETTE[highlights.len() % PALETTE.len()])
+}
+
+/// The pinned patterns with their numbers and colors, as shown by `:hl`.
+pub fn list(highlights: &[Highlight]) -> String {
+    if highlights.is_empty() {
+        return String::from("No highlights");
+    }
+    let entries: Vec<String> = highlights
+        .iter()
+        .enumerate()
+        .map(|(i, h)| {
+            format!("{}:{} ({})", i + 1, h.pattern, color_name(h.color))
+        })
+        .collect();
+    entries.join("  ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_list() {
+        let options = SearchOptions::default();
+        let mut highlights = Vec::new();
+        assert_eq!(list(&highlights), "No highlights");
+
+        highlights.push(Highlight::new("ERROR", 3, options).unwrap());
+        highlights.push(Highlight::new("req-4[0-9]", 1, options).unwrap());
+        assert_eq!(list(&highlights), "1:ERROR (yellow)  2:req-4[0-9] (red)");
+
+        assert!(Highlight::new("a(b", 3, options).is_err());
+    }
+
+    #[test]
+    fn test_colors() {
+        let options = SearchOptions::default();
+        assert_eq!(color_by_name("Red"), Some(1));
+        assert_eq!(color_by_name("pink"), None);
+
+        let mut highlights = Vec::new();
+        for _ in 0..3 {
+            let color = next_color(&highlights);
+            highlights.push(Highlight::new("a", color, options).unwrap());
+        }
+        let colors: Vec<u8> = highlights.iter().map(|h| h.color).collect();
+        assert_eq!(colors, [3, 6, 2]);
+
+        // The color of an unpinned pattern is given out again, the others
+        // keep theirs.
+        highlights.remove(0);
+        assert_eq!(next_color(&highlights), 3);
+
+        for _ in 0..4 {
+            let color = next_color(&highlights);
+            highlights.push(Highlight::new("a", color, options).unwrap());
+        }
+        assert_eq!(next_color(&highlights), PALETTE[0]);
+    }
+}
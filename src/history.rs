@@ -0,0 +1,182 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// How many entries of each kind are kept.
+pub const HISTORY_SIZE: usize = 100;
+
+/// Earlier entries of a prompt, oldest first, which can be stepped through
+/// with the arrow keys.
+#[derive(Debug, Default)]
+pub struct History {
+    entries: Vec<String>,
+    /// The entry being shown while stepping through them.
+    pos: Option<usize>,
+    /// What was typed before stepping back, shown again after the newest
+    /// entry.
+    draft: String,
+}
+
+impl History {
+    pub fn new() -> History {
+        History::default()
+    }
+
+    /// Adds `entry` as the newest one. An entry that is already there is
+    /// moved instead, and the oldest ones are dropped past `HISTORY_SIZE`.
+    pub fn add(&mut self, entry: &str) {
+        self.reset();
+        if entry.is_empty() {
+            return;
+        }
+        self.entries.retain(|e| e != entry);
+        self.entries.push(entry.to_string());
+        if self.entries.len() > HISTORY_SIZE {
+            let excess = self.entries.len() - HISTORY_SIZE;
+            self.entries.drain(..excess);
+        }
+    }
+
+    /// Steps back to the entry before the one shown, `current` is what is
+    /// typed in the prompt. `None` when there are no older entries.
+    pub fn previous(&mut self, current: &str) -> Option<&str> {
+        let pos = match self.pos {
+            Some(0) => return None,
+            Some(pos) => pos - 1,
+            None if self.entries.is_empty() => return None,
+            None => {
+                self.draft = current.to_string();
+                self.entries.len() - 1
+            }
+        };
+        self.pos = Some(pos);
+        Some(&self.entries[pos])
+    }
+
+    /// Steps forward to the entry after the one shown, and past the newest
+    /// back to what was typed. `None` when not stepping through entries.
+    pub fn next(&mut self) -> Option<&str> {
+        let pos = self.pos?;
+        if pos + 1 < self.entries.len() {
+            self.pos = Some(pos + 1);
+            Some(&self.entries[pos + 1])
+        } else {
+            self.pos = None;
+            Some(&self.draft)
+        }
+    }
+
+    /// Stops stepping through the entries.
+    pub fn reset(&mut self) {
+        self.pos = None;
+        self.draft.clear();
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+}
+
+/// Where the history is kept between sessions:
+/// `$XDG_STATE_HOME/less2/history`, or `~/.local/state/less2/history`.
+pub fn history_path() -> Option<PathBuf> {
+    let state = match env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".local/state"),
+    };
+    Some(state.join("less2").join("history"))
+}
+
+/// Reads the search and command histories from `path`. Every line is an
+/// entry, starting with `/` for searches and `:` for commands.
+pub fn load(path: &Path) -> (History, History) {
+    let mut searches = History::new();
+    let mut commands = History::new();
+    if let Ok(text) = fs::read_to_string(path) {
+        for line in text.lines() {
+            if let Some(entry) = line.strip_prefix('/') {
+                searches.add(entry);
+            } else if let Some(entry) = line.strip_prefix(':') {
+                commands.add(entry);
+            }
+        }
+    }
+    (searches, commands)
+}
+
+/// Writes both histories to `path`, creating its directory if needed.
+pub fn save(
+    path: &Path,
+    searches: &History,
+    commands: &History,
+) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut text = String::new();
+    for entry in searches.entries() {
+        text.push('/');
+        text.push_str(entry);
+        text.push('\n');
+    }
+    for entry in commands.entries() {
+        text.push(':');
+        text.push_str(entry);
+        text.push('\n');
+    }
+    fs::write(path, text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add() {
+        let mut history = History::new();
+        history.add("a");
+        history.add("b");
+        history.add("a");
+        history.add("");
+        assert_eq!(history.entries(), ["b", "a"]);
+
+        for i in 0..HISTORY_SIZE {
+            history.add(&i.to_string());
+        }
+        assert_eq!(history.entries().len(), HISTORY_SIZE);
+        assert_eq!(history.entries()[0], "0");
+    }
+
+    #[test]
+    fn test_step() {
+        let mut history = History::new();
+        assert_eq!(history.previous("x"), None);
+        history.add("a");
+        history.add("b");
+
+        assert_eq!(history.previous("typed"), Some("b"));
+        assert_eq!(history.previous("b"), Some("a"));
+        assert_eq!(history.previous("a"), None);
+        assert_eq!(history.next(), Some("b"));
+        assert_eq!(history.next(), Some("typed"));
+        assert_eq!(history.next(), None);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = env::temp_dir()
+            .join(format!("less2-test-{}", std::process::id()))
+            .join("history");
+        let mut searches = History::new();
+        searches.add("err.*");
+        let mut commands = History::new();
+        commands.add("hl x");
+        save(&path, &searches, &commands).unwrap();
+
+        let (searches, commands) = load(&path);
+        assert_eq!(searches.entries(), ["err.*"]);
+        assert_eq!(commands.entries(), ["hl x"]);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
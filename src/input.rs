@@ -4,14 +4,33 @@ use termion::event::Key;
 //use termion::event::MouseEvent;
 use termion::input::TermRead;
 
-use std::io::stdin;
+use std::io::{self, stdin};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+use history::{self, History};
+use interrupt;
+use line_editor::LineEditor;
+use searcher::SearchOptions;
 
 #[derive(Debug)]
 pub enum UserInput {
     Ctrl(char),
+    Alt(char),
     Char(char),
     Num(char),
     Backspace,
+    Delete,
+    Esc,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
     NoOp,
 }
 
@@ -28,31 +47,186 @@ pub enum Command {
     JumpBeginning,
     JumpEnd,
     JumpPercent(u64),
+    JumpLine(u64),
     JumpNextMatch(bool),
-
-    Search(String, bool),
+    /// Like `JumpNextMatch`, but goes on into the next or previous file
+    /// when there are no more matches in this one.
+    JumpNextMatchAcrossFiles(bool),
+
+    /// Columns to scroll, 0 means half a screen.
+    ScrollLeft(usize),
+    ScrollRight(usize),
+
+    Search(String, bool, SearchOptions),
+    /// The search pattern has been edited, but not entered yet.
+    SearchPreview(String, bool, SearchOptions),
+    /// The search prompt was left without searching.
+    SearchCancel,
+    /// Show only the lines matching a pattern, or those that don't if the
+    /// flag is set. An empty pattern shows all lines again.
+    Filter(String, bool, SearchOptions),
+    /// Pin a pattern so it stays highlighted, in a color of its own, or
+    /// the one named.
+    Highlight(String, Option<String>, SearchOptions),
+    ListHighlights,
+    /// Unpin the highlight with this number, or all of them.
+    RemoveHighlight(Option<usize>),
+
+    Follow,
+    ToggleOption(char),
 
     NextFile,
+    PreviousFile,
+    /// Switch to the file with this one indexed position in the list.
+    JumpFile(usize),
+    /// Add a file to the list and view it.
+    OpenFile(String),
+    /// Take the current file out of the list.
+    CloseFile,
+    ListFiles,
 
     Quit,
     NoOp,
 }
 
-pub fn get_input() -> UserInput {
-    let stdin = stdin();
-    for c in stdin.events() {
-        return match c.unwrap() {
-            Event::Key(Key::Char(c)) => parse_char(c),
+/// Something the main loop should react to.
+#[derive(Debug)]
+pub enum InputEvent {
+    Key(UserInput),
+    /// The terminal has changed size.
+    Resize,
+    /// Nothing happened within the requested timeout.
+    Tick,
+    /// The input thread has stopped, no more events will arrive.
+    Closed,
+}
 
-            Event::Key(Key::Ctrl(c)) => UserInput::Ctrl(c),
+/// The write end of the pipe the SIGWINCH handler wakes the resize thread
+/// through.
+static RESIZE_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn on_resize(_signal: libc::c_int) {
+    let fd = RESIZE_PIPE.load(Ordering::SeqCst);
+    if fd >= 0 {
+        let byte: u8 = 0;
+        // Only async-signal-safe calls are allowed here.
+        unsafe {
+            libc::write(fd, &byte as *const u8 as *const libc::c_void, 1);
+        }
+    }
+}
 
-            Event::Key(Key::Backspace) => UserInput::Backspace,
+/// Reads key presses and terminal resizes on separate threads so the main
+/// loop can wake up without user input, e.g. to redraw a file that is being
+/// followed. Ctrl-C is also flagged in `interrupt` as soon as it's read.
+pub struct Events {
+    receiver: Receiver<InputEvent>,
+}
 
-            //    Event::Mouse(MouseEvent::Press(MouseButton::WheelUp, _, _))
-            _ => UserInput::NoOp,
-        };
+impl Events {
+    pub fn new() -> Events {
+        let (sender, receiver) = channel();
+
+        let key_sender = sender.clone();
+        thread::spawn(move || {
+            let stdin = stdin();
+            for c in stdin.events() {
+                // Flag the interrupt right away, the main loop may be busy
+                // with what should be interrupted.
+                if let Ok(Event::Key(Key::Ctrl('c'))) = c {
+                    interrupt::interrupt();
+                }
+                let input = match c {
+                    Ok(event) => parse_event(event),
+                    Err(_) => UserInput::NoOp,
+                };
+                if key_sender.send(InputEvent::Key(input)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        listen_for_resize(sender);
+
+        Events { receiver }
+    }
+
+    /// Blocks until an event arrives, or until `timeout` has passed if one
+    /// is given.
+    pub fn next(&self, timeout: Option<Duration>) -> InputEvent {
+        match timeout {
+            Some(timeout) => match self.receiver.recv_timeout(timeout) {
+                Ok(event) => event,
+                Err(RecvTimeoutError::Timeout) => InputEvent::Tick,
+                Err(RecvTimeoutError::Disconnected) => InputEvent::Closed,
+            },
+            None => match self.receiver.recv() {
+                Ok(event) => event,
+                Err(_) => InputEvent::Closed,
+            },
+        }
+    }
+}
+
+/// Installs a SIGWINCH handler, and sends a `Resize` event every time it
+/// fires. Resizes aren't noticed if the pipe can't be created.
+fn listen_for_resize(sender: Sender<InputEvent>) {
+    let mut fds = [0 as libc::c_int; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return;
+    }
+    let (read_fd, write_fd) = (fds[0], fds[1]);
+
+    RESIZE_PIPE.store(write_fd, Ordering::SeqCst);
+    unsafe {
+        let handler: extern "C" fn(libc::c_int) = on_resize;
+        libc::signal(libc::SIGWINCH, handler as libc::sighandler_t);
+    }
+
+    thread::spawn(move || {
+        let mut buf = [0u8; 64];
+        loop {
+            let n = unsafe {
+                libc::read(
+                    read_fd,
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                )
+            };
+            if n < 0
+                && io::Error::last_os_error().kind()
+                    == io::ErrorKind::Interrupted
+            {
+                continue;
+            }
+            // Several signals in a row only need one redraw.
+            if n <= 0 || sender.send(InputEvent::Resize).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+fn parse_event(event: Event) -> UserInput {
+    match event {
+        Event::Key(Key::Char(c)) => parse_char(c),
+
+        Event::Key(Key::Ctrl(c)) => UserInput::Ctrl(c),
+        Event::Key(Key::Alt(c)) => UserInput::Alt(c),
+
+        Event::Key(Key::Backspace) => UserInput::Backspace,
+        Event::Key(Key::Delete) => UserInput::Delete,
+        Event::Key(Key::Esc) => UserInput::Esc,
+        Event::Key(Key::Left) => UserInput::Left,
+        Event::Key(Key::Right) => UserInput::Right,
+        Event::Key(Key::Up) => UserInput::Up,
+        Event::Key(Key::Down) => UserInput::Down,
+        Event::Key(Key::Home) => UserInput::Home,
+        Event::Key(Key::End) => UserInput::End,
+
+        //    Event::Mouse(MouseEvent::Press(MouseButton::WheelUp, _, _))
+        _ => UserInput::NoOp,
     }
-    UserInput::NoOp
 }
 
 fn parse_char(c: char) -> UserInput {
@@ -66,12 +240,27 @@ fn parse_char(c: char) -> UserInput {
 enum Mode {
     Normal,
     Search,
+    /// After `&`, typing a filter pattern.
+    Filter,
+    Colon,
+    /// After `-`, waiting for the option letter to toggle.
+    Option,
 }
 
 pub struct CommandLine {
     mode: Mode,
     is_forward: bool,
-    buffer: String,
+    buffer: LineEditor,
+    /// What every search starts out with, set by command line options.
+    pub default_search_options: SearchOptions,
+    /// The modifiers of the search being typed.
+    search_options: SearchOptions,
+    /// Patterns entered for searches and filters.
+    search_history: History,
+    /// Text entered after `:`.
+    command_history: History,
+    /// Where the histories are saved, if anywhere.
+    history_file: Option<PathBuf>,
 }
 
 impl CommandLine {
@@ -79,14 +268,31 @@ impl CommandLine {
         CommandLine {
             mode: Mode::Normal,
             is_forward: true,
-            buffer: String::new(),
+            buffer: LineEditor::new(),
+            default_search_options: SearchOptions::default(),
+            search_options: SearchOptions::default(),
+            search_history: History::new(),
+            command_history: History::new(),
+            history_file: None,
         }
     }
 
+    /// Loads the histories from `path`, and saves them there whenever
+    /// something is entered.
+    pub fn set_history_file(&mut self, path: PathBuf) {
+        let (searches, commands) = history::load(&path);
+        self.search_history = searches;
+        self.command_history = commands;
+        self.history_file = Some(path);
+    }
+
     pub fn parse_input(&mut self, input: &UserInput) -> Command {
         return match self.mode {
             Mode::Normal => self.normal_parse(input),
-            Mode::Search => self.search_parse(input),
+            Mode::Search | Mode::Filter | Mode::Colon => {
+                self.prompt_parse(input)
+            }
+            Mode::Option => self.option_parse(input),
         };
     }
 
@@ -105,31 +311,46 @@ impl CommandLine {
             Char('j') => Command::DownOneLine,
             Char('k') => Command::UpOneLine,
 
+            Char('g') | Char('G') if self.number() > 0 => {
+                Command::JumpLine(self.number())
+            }
             Char('g') => Command::JumpBeginning,
             Char('G') => Command::JumpEnd,
+            Char('F') => Command::Follow,
             Char('p') => Command::JumpPercent(self.number()),
 
+            Left | Alt('(') => Command::ScrollLeft(self.number() as usize),
+            Right | Alt(')') => Command::ScrollRight(self.number() as usize),
+
             Char('n') => Command::JumpNextMatch(self.is_forward),
             Char('N') => Command::JumpNextMatch(!self.is_forward),
+            Alt('n') => Command::JumpNextMatchAcrossFiles(self.is_forward),
+            Alt('N') => Command::JumpNextMatchAcrossFiles(!self.is_forward),
 
             Char('/') => {
                 self.is_forward = true;
-                self.mode = Mode::Search;
-                Command::NoOp
+                self.enter_prompt(Mode::Search)
             }
             Char('?') => {
                 self.is_forward = false;
-                self.mode = Mode::Search;
-                Command::NoOp
+                self.enter_prompt(Mode::Search)
             }
+            Char('&') => self.enter_prompt(Mode::Filter),
+            Char(':') => self.enter_prompt(Mode::Colon),
+            Char('-') => self.enter_prompt(Mode::Option),
 
             Num(c) => {
-                self.buffer.push(*c);
+                self.buffer.insert(*c);
                 Command::NoOp
             }
 
             Char('>') => Command::NextFile,
 
+            // Cancels a count being typed.
+            Ctrl('c') => {
+                self.buffer.clear();
+                Command::NoOp
+            }
             Ctrl(_) => Command::NoOp,
             _ => Command::NoOp,
         };
@@ -140,41 +361,175 @@ impl CommandLine {
         command
     }
 
-    fn search_parse(&mut self, input: &UserInput) -> Command {
-        let command = match input {
-            // TODO: This exits the program. I don't understand why.
-            UserInput::Ctrl('c') => {
+    fn enter_prompt(&mut self, mode: Mode) -> Command {
+        self.buffer.clear();
+        self.search_history.reset();
+        self.command_history.reset();
+        self.search_options = self.default_search_options;
+        self.mode = mode;
+        Command::NoOp
+    }
+
+    fn option_parse(&mut self, input: &UserInput) -> Command {
+        self.mode = Mode::Normal;
+        match input {
+            UserInput::Char(c) if c.is_alphabetic() => {
+                Command::ToggleOption(*c)
+            }
+            _ => Command::NoOp,
+        }
+    }
+
+    fn prompt_parse(&mut self, input: &UserInput) -> Command {
+        match input {
+            // What was running is stopped by the interrupt, see `Events`.
+            UserInput::Ctrl('c') | UserInput::Esc => {
+                self.buffer.clear();
+                let mode = self.mode.clone();
                 self.mode = Mode::Normal;
-                Command::NoOp
+                match mode {
+                    Mode::Search => Command::SearchCancel,
+                    _ => Command::NoOp,
+                }
             }
 
             UserInput::Char('\n') => {
-                let pattern = self.buffer.clone();
+                let text = self.buffer.text().to_string();
                 self.buffer.clear();
+                self.remember(&text);
+                let mode = self.mode.clone();
                 self.mode = Mode::Normal;
-                Command::Search(pattern, self.is_forward)
+                match mode {
+                    Mode::Colon => {
+                        parse_colon_command(&text, self.default_search_options)
+                    }
+                    Mode::Filter => {
+                        let (pattern, invert) = match text.strip_prefix('!') {
+                            Some(pattern) => (pattern.to_string(), true),
+                            None => (text.clone(), false),
+                        };
+                        Command::Filter(pattern, invert, self.search_options)
+                    }
+                    _ => Command::Search(
+                        text,
+                        self.is_forward,
+                        self.search_options,
+                    ),
+                }
             }
 
-            UserInput::Backspace => {
-                self.buffer.pop();
-                Command::NoOp
-            }
+            UserInput::Ctrl(c) => match (&self.mode, c) {
+                (Mode::Search, 'r') | (Mode::Filter, 'r') => {
+                    self.search_options.literal = !self.search_options.literal;
+                    self.preview()
+                }
+                (Mode::Search, 't') | (Mode::Filter, 't') => {
+                    self.search_options.cycle_case();
+                    self.preview()
+                }
+                (Mode::Search, 'x') | (Mode::Filter, 'x') => {
+                    self.search_options.word = !self.search_options.word;
+                    self.preview()
+                }
+                (_, 'a') => self.edit(LineEditor::home),
+                (_, 'e') => self.edit(LineEditor::end),
+                (_, 'w') => self.edit(LineEditor::delete_word),
+                (_, 'u') => self.edit(LineEditor::delete_to_start),
+                (_, 'k') => self.edit(LineEditor::delete_to_end),
+                _ => Command::NoOp,
+            },
 
-            UserInput::Char(c) => {
-                self.buffer.push(*c);
-                Command::NoOp
-            }
+            UserInput::Left => self.edit(LineEditor::left),
+            UserInput::Right => self.edit(LineEditor::right),
+            UserInput::Home => self.edit(LineEditor::home),
+            UserInput::End => self.edit(LineEditor::end),
 
-            UserInput::Num(c) => {
-                self.buffer.push(*c);
-                Command::NoOp
+            UserInput::Up => self.step_history(true),
+            UserInput::Down => self.step_history(false),
+
+            UserInput::Backspace => self.edit(LineEditor::backspace),
+            UserInput::Delete => self.edit(LineEditor::delete),
+
+            UserInput::Char(c) | UserInput::Num(c) => {
+                self.buffer.insert(*c);
+                self.preview()
             }
 
             _ => Command::NoOp,
+        }
+    }
+
+    /// The history of what is being typed.
+    fn history(&mut self) -> Option<&mut History> {
+        match self.mode {
+            Mode::Search | Mode::Filter => Some(&mut self.search_history),
+            Mode::Colon => Some(&mut self.command_history),
+            Mode::Normal | Mode::Option => None,
+        }
+    }
+
+    /// Puts an older history entry in the prompt, or a newer one.
+    fn step_history(&mut self, back: bool) -> Command {
+        let typed = self.buffer.text().to_string();
+        let entry = match self.history() {
+            Some(history) if back => history.previous(&typed).map(String::from),
+            Some(history) => history.next().map(String::from),
+            None => None,
         };
-        if command != Command::NoOp {}
+        match entry {
+            Some(entry) => {
+                self.buffer.set(&entry);
+                self.preview()
+            }
+            None => Command::NoOp,
+        }
+    }
 
-        command
+    /// Adds `text` to the history of the prompt, and saves the histories.
+    fn remember(&mut self, text: &str) {
+        match self.history() {
+            Some(history) => history.add(text),
+            None => return,
+        }
+        if let Some(path) = &self.history_file {
+            // Not being able to save the history shouldn't get in the way.
+            let _ = history::save(
+                path,
+                &self.search_history,
+                &self.command_history,
+            );
+        }
+    }
+
+    /// Applies `edit` to the prompt, and previews the result if the text
+    /// changed.
+    fn edit(&mut self, edit: fn(&mut LineEditor)) -> Command {
+        let before = self.buffer.text().len();
+        edit(&mut self.buffer);
+        if self.buffer.text().len() == before {
+            return Command::NoOp;
+        }
+        self.preview()
+    }
+
+    /// Lets the controller search for the pattern as it is typed.
+    fn preview(&self) -> Command {
+        match self.mode {
+            Mode::Search => Command::SearchPreview(
+                self.buffer.text().to_string(),
+                self.is_forward,
+                self.search_options,
+            ),
+            _ => Command::NoOp,
+        }
+    }
+
+    /// True when no command is being typed.
+    pub fn is_idle(&self) -> bool {
+        match self.mode {
+            Mode::Normal => self.buffer.is_empty(),
+            Mode::Search | Mode::Filter | Mode::Colon | Mode::Option => false,
+        }
     }
 
     pub fn text(&self) -> String {
@@ -184,18 +539,342 @@ impl CommandLine {
                 n => format!(":{}", n),
             },
             Mode::Search => match self.is_forward {
-                true => format!("/{}", self.buffer),
-                false => format!("?{}", self.buffer),
+                true => format!(
+                    "{}/{}",
+                    self.search_options.describe(),
+                    self.buffer.text()
+                ),
+                false => format!(
+                    "{}?{}",
+                    self.search_options.describe(),
+                    self.buffer.text()
+                ),
             },
+            Mode::Filter => {
+                format!(
+                    "{}&{}",
+                    self.search_options.describe(),
+                    self.buffer.text()
+                )
+            }
+            Mode::Colon => format!(":{}", self.buffer.text()),
+            Mode::Option => "-".to_string(),
         };
     }
 
+    /// The byte offset of the cursor in `text`.
+    pub fn cursor(&self) -> usize {
+        let text = self.text();
+        match self.mode {
+            Mode::Search | Mode::Filter | Mode::Colon => {
+                text.len() - self.buffer.text().len() + self.buffer.cursor()
+            }
+            Mode::Normal | Mode::Option => text.len(),
+        }
+    }
+
     fn number(&self) -> u64 {
         let mut tot = 0;
-        for (i, c) in self.buffer.chars().enumerate() {
+        let buffer = self.buffer.text();
+        for (i, c) in buffer.chars().enumerate() {
             tot += c.to_digit(10).unwrap() as u64
-                * 10u64.pow((self.buffer.len() - i - 1) as u32);
+                * 10u64.pow((buffer.len() - i - 1) as u32);
         }
         tot
     }
 }
+
+/// Parses the text entered after `:`. Patterns are matched with `options`.
+fn parse_colon_command(text: &str, options: SearchOptions) -> Command {
+    let text = text.trim();
+    let (name, arg) = match text.find(' ') {
+        Some(i) => (&text[..i], text[i + 1..].trim()),
+        None => (text, ""),
+    };
+    match name {
+        "hl" if arg.is_empty() => return Command::ListHighlights,
+        "hl" => {
+            let (color, pattern) = match arg.strip_prefix("-c ") {
+                Some(rest) => match rest.trim_start().split_once(' ') {
+                    Some((color, pattern)) => {
+                        (Some(color.to_string()), pattern.trim_start())
+                    }
+                    None => return Command::NoOp,
+                },
+                None => (None, arg),
+            };
+            return Command::Highlight(pattern.to_string(), color, options);
+        }
+        "unhl" if arg.is_empty() => return Command::RemoveHighlight(None),
+        "unhl" => {
+            return match arg.parse::<usize>() {
+                Ok(n) => Command::RemoveHighlight(Some(n)),
+                Err(_) => Command::NoOp,
+            }
+        }
+        "n" => return Command::NextFile,
+        "p" => return Command::PreviousFile,
+        "x" if arg.is_empty() => return Command::JumpFile(1),
+        "x" => {
+            return match arg.parse::<usize>() {
+                Ok(n) => Command::JumpFile(n),
+                Err(_) => Command::NoOp,
+            }
+        }
+        "e" if !arg.is_empty() => return Command::OpenFile(arg.to_string()),
+        "d" => return Command::CloseFile,
+        "ls" => return Command::ListFiles,
+        _ => (),
+    }
+
+    match text.parse::<u64>() {
+        Ok(line) if line > 0 => Command::JumpLine(line),
+        Ok(_) => Command::JumpBeginning,
+        Err(_) => Command::NoOp,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use searcher::CaseMode;
+
+    fn parse_all(command_line: &mut CommandLine, keys: &str) -> Command {
+        let mut command = Command::NoOp;
+        for c in keys.chars() {
+            command = command_line.parse_input(&parse_char(c));
+        }
+        command
+    }
+
+    #[test]
+    fn test_count_before_g_jumps_to_line() {
+        let mut command_line = CommandLine::new();
+        assert_eq!(parse_all(&mut command_line, "42g"), Command::JumpLine(42));
+        assert_eq!(parse_all(&mut command_line, "7G"), Command::JumpLine(7));
+        assert_eq!(parse_all(&mut command_line, "g"), Command::JumpBeginning);
+        assert_eq!(parse_all(&mut command_line, "G"), Command::JumpEnd);
+
+        parse_all(&mut command_line, "12");
+        command_line.parse_input(&UserInput::Ctrl('c'));
+        assert!(command_line.is_idle());
+        assert_eq!(parse_all(&mut command_line, "g"), Command::JumpBeginning);
+    }
+
+    #[test]
+    fn test_colon_line_number() {
+        let mut command_line = CommandLine::new();
+        assert_eq!(
+            parse_all(&mut command_line, ":12\n"),
+            Command::JumpLine(12)
+        );
+        assert_eq!(parse_all(&mut command_line, ":abc\n"), Command::NoOp);
+        assert!(command_line.is_idle());
+    }
+
+    #[test]
+    fn test_colon_highlight() {
+        let mut command_line = CommandLine::new();
+        let options = SearchOptions::default();
+        assert_eq!(
+            parse_all(&mut command_line, ":hl req 42\n"),
+            Command::Highlight(String::from("req 42"), None, options)
+        );
+        assert_eq!(
+            parse_all(&mut command_line, ":hl -c red ERROR\n"),
+            Command::Highlight(
+                String::from("ERROR"),
+                Some(String::from("red")),
+                options
+            )
+        );
+        assert_eq!(parse_all(&mut command_line, ":hl -c red\n"), Command::NoOp);
+        assert_eq!(
+            parse_all(&mut command_line, ":hl\n"),
+            Command::ListHighlights
+        );
+        assert_eq!(
+            parse_all(&mut command_line, ":unhl 2\n"),
+            Command::RemoveHighlight(Some(2))
+        );
+        assert_eq!(
+            parse_all(&mut command_line, ":unhl\n"),
+            Command::RemoveHighlight(None)
+        );
+        assert_eq!(parse_all(&mut command_line, ":unhl x\n"), Command::NoOp);
+    }
+
+    #[test]
+    fn test_colon_files() {
+        let mut command_line = CommandLine::new();
+        assert_eq!(parse_all(&mut command_line, ":n\n"), Command::NextFile);
+        assert_eq!(parse_all(&mut command_line, ":p\n"), Command::PreviousFile);
+        assert_eq!(parse_all(&mut command_line, ":x\n"), Command::JumpFile(1));
+        assert_eq!(
+            parse_all(&mut command_line, ":x 3\n"),
+            Command::JumpFile(3)
+        );
+        assert_eq!(
+            parse_all(&mut command_line, ":e logs/a b.txt\n"),
+            Command::OpenFile(String::from("logs/a b.txt"))
+        );
+        assert_eq!(parse_all(&mut command_line, ":e\n"), Command::NoOp);
+        assert_eq!(parse_all(&mut command_line, ":d\n"), Command::CloseFile);
+        assert_eq!(parse_all(&mut command_line, ":ls\n"), Command::ListFiles);
+    }
+
+    #[test]
+    fn test_horizontal_scroll() {
+        let mut command_line = CommandLine::new();
+        assert_eq!(
+            command_line.parse_input(&UserInput::Alt(')')),
+            Command::ScrollRight(0)
+        );
+        parse_all(&mut command_line, "12");
+        assert_eq!(
+            command_line.parse_input(&UserInput::Left),
+            Command::ScrollLeft(12)
+        );
+    }
+
+    #[test]
+    fn test_incremental_search() {
+        let mut command_line = CommandLine::new();
+        let options = SearchOptions::default();
+        assert_eq!(
+            parse_all(&mut command_line, "?ab"),
+            Command::SearchPreview(String::from("ab"), false, options)
+        );
+        assert_eq!(
+            command_line.parse_input(&UserInput::Backspace),
+            Command::SearchPreview(String::from("a"), false, options)
+        );
+        assert_eq!(
+            command_line.parse_input(&UserInput::Esc),
+            Command::SearchCancel
+        );
+        assert!(command_line.is_idle());
+
+        assert_eq!(
+            parse_all(&mut command_line, "/x\n"),
+            Command::Search(String::from("x"), true, options)
+        );
+        assert_eq!(parse_all(&mut command_line, ":1"), Command::NoOp);
+        assert_eq!(command_line.parse_input(&UserInput::Esc), Command::NoOp);
+    }
+
+    #[test]
+    fn test_search_across_files() {
+        let mut command_line = CommandLine::new();
+        parse_all(&mut command_line, "?x\n");
+        assert_eq!(
+            command_line.parse_input(&UserInput::Alt('n')),
+            Command::JumpNextMatchAcrossFiles(false)
+        );
+        assert_eq!(
+            command_line.parse_input(&UserInput::Alt('N')),
+            Command::JumpNextMatchAcrossFiles(true)
+        );
+    }
+
+    #[test]
+    fn test_history() {
+        let mut command_line = CommandLine::new();
+        let options = SearchOptions::default();
+        parse_all(&mut command_line, "/one\n/two\n:5\n/");
+        parse_all(&mut command_line, "x");
+
+        assert_eq!(
+            command_line.parse_input(&UserInput::Up),
+            Command::SearchPreview(String::from("two"), true, options)
+        );
+        command_line.parse_input(&UserInput::Up);
+        assert_eq!(command_line.text(), "/one");
+        command_line.parse_input(&UserInput::Down);
+        command_line.parse_input(&UserInput::Down);
+        assert_eq!(command_line.text(), "/x");
+
+        // Commands have a history of their own.
+        parse_all(&mut command_line, "\n:");
+        command_line.parse_input(&UserInput::Up);
+        assert_eq!(command_line.text(), ":5");
+    }
+
+    #[test]
+    fn test_prompt_editing() {
+        let mut command_line = CommandLine::new();
+        let options = SearchOptions::default();
+        parse_all(&mut command_line, "/ac");
+        command_line.parse_input(&UserInput::Left);
+        assert_eq!(
+            parse_all(&mut command_line, "b"),
+            Command::SearchPreview(String::from("abc"), true, options)
+        );
+        assert_eq!(command_line.cursor(), 3);
+
+        // Moving the cursor doesn't search again.
+        assert_eq!(
+            command_line.parse_input(&UserInput::Ctrl('a')),
+            Command::NoOp
+        );
+        assert_eq!(command_line.cursor(), 1);
+        assert_eq!(
+            command_line.parse_input(&UserInput::Ctrl('k')),
+            Command::SearchPreview(String::new(), true, options)
+        );
+    }
+
+    #[test]
+    fn test_search_modifiers() {
+        let mut command_line = CommandLine::new();
+        command_line.default_search_options.case = CaseMode::Smart;
+        parse_all(&mut command_line, "/a");
+
+        let options = SearchOptions {
+            case: CaseMode::Smart,
+            literal: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            command_line.parse_input(&UserInput::Ctrl('r')),
+            Command::SearchPreview(String::from("a"), true, options)
+        );
+        assert_eq!(command_line.text(), "Smart-case Regex-off /a");
+
+        // Modifiers only last for one search.
+        parse_all(&mut command_line, "\n/");
+        assert_eq!(command_line.text(), "Smart-case /");
+    }
+
+    #[test]
+    fn test_filter() {
+        let mut command_line = CommandLine::new();
+        let options = SearchOptions::default();
+        assert_eq!(parse_all(&mut command_line, "&err"), Command::NoOp);
+        assert_eq!(command_line.text(), "&err");
+        assert_eq!(
+            parse_all(&mut command_line, "\n"),
+            Command::Filter(String::from("err"), false, options)
+        );
+        assert_eq!(
+            parse_all(&mut command_line, "&!debug\n"),
+            Command::Filter(String::from("debug"), true, options)
+        );
+        assert_eq!(
+            parse_all(&mut command_line, "&\n"),
+            Command::Filter(String::new(), false, options)
+        );
+    }
+
+    #[test]
+    fn test_toggle_option() {
+        let mut command_line = CommandLine::new();
+        assert_eq!(
+            parse_all(&mut command_line, "-N"),
+            Command::ToggleOption('N')
+        );
+        assert_eq!(parse_all(&mut command_line, "-\n"), Command::NoOp);
+        assert!(command_line.is_idle());
+    }
+}
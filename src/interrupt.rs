@@ -0,0 +1,28 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set by Ctrl-C, from the input thread or a SIGINT handler. Cleared once
+/// the controller has stopped whatever was running.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_sigint(_signal: libc::c_int) {
+    interrupt();
+}
+
+/// Makes SIGINT interrupt like Ctrl-C does, instead of killing the pager.
+/// In raw mode the terminal sends Ctrl-C as a key, but the signal can
+/// still come from elsewhere.
+pub fn catch_sigint() {
+    unsafe {
+        let handler: extern "C" fn(libc::c_int) = on_sigint;
+        libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+    }
+}
+
+pub fn interrupt() {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// True if there has been an interrupt since the last call.
+pub fn take() -> bool {
+    INTERRUPTED.swap(false, Ordering::SeqCst)
+}
@@ -0,0 +1,166 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+/// The text typed at a prompt, with a cursor to move around and edit at.
+#[derive(Debug, Default, Clone)]
+pub struct LineEditor {
+    text: String,
+    /// Byte offset into `text`, always at a grapheme boundary.
+    cursor: usize,
+}
+
+impl LineEditor {
+    pub fn new() -> LineEditor {
+        LineEditor::default()
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.cursor = 0;
+    }
+
+    /// Replaces the text, with the cursor at the end.
+    pub fn set(&mut self, text: &str) {
+        self.text = text.to_string();
+        self.cursor = self.text.len();
+    }
+
+    pub fn insert(&mut self, c: char) {
+        self.text.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+    }
+
+    /// Deletes the grapheme before the cursor.
+    pub fn backspace(&mut self) {
+        let start = self.previous_boundary();
+        self.text.replace_range(start..self.cursor, "");
+        self.cursor = start;
+    }
+
+    /// Deletes the grapheme under the cursor.
+    pub fn delete(&mut self) {
+        let end = self.next_boundary();
+        self.text.replace_range(self.cursor..end, "");
+    }
+
+    pub fn left(&mut self) {
+        self.cursor = self.previous_boundary();
+    }
+
+    pub fn right(&mut self) {
+        self.cursor = self.next_boundary();
+    }
+
+    pub fn home(&mut self) {
+        self.cursor = 0;
+    }
+
+    pub fn end(&mut self) {
+        self.cursor = self.text.len();
+    }
+
+    /// Deletes the word before the cursor, and the whitespace after it.
+    pub fn delete_word(&mut self) {
+        let before = &self.text[..self.cursor];
+        let word_end = before.trim_end().len();
+        let start = match before[..word_end].rfind(char::is_whitespace) {
+            Some(i) => i + before[i..].chars().next().unwrap().len_utf8(),
+            None => 0,
+        };
+        self.text.replace_range(start..self.cursor, "");
+        self.cursor = start;
+    }
+
+    /// Deletes everything before the cursor.
+    pub fn delete_to_start(&mut self) {
+        self.text.replace_range(..self.cursor, "");
+        self.cursor = 0;
+    }
+
+    /// Deletes everything from the cursor on.
+    pub fn delete_to_end(&mut self) {
+        self.text.truncate(self.cursor);
+    }
+
+    fn previous_boundary(&self) -> usize {
+        match self.text[..self.cursor].grapheme_indices(true).next_back() {
+            Some((i, _)) => i,
+            None => 0,
+        }
+    }
+
+    fn next_boundary(&self) -> usize {
+        match self.text[self.cursor..].graphemes(true).next() {
+            Some(g) => self.cursor + g.len(),
+            None => self.cursor,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_text(text: &str) -> LineEditor {
+        let mut editor = LineEditor::new();
+        editor.set(text);
+        editor
+    }
+
+    #[test]
+    fn test_move_and_insert() {
+        let mut editor = with_text("ac");
+        editor.left();
+        editor.insert('b');
+        assert_eq!(editor.text(), "abc");
+        editor.home();
+        editor.insert('>');
+        editor.end();
+        editor.insert('<');
+        assert_eq!(editor.text(), ">abc<");
+    }
+
+    #[test]
+    fn test_graphemes() {
+        // The accent is a combining character, deleted along with the e.
+        let mut editor = with_text("xe\u{301}y");
+        editor.left();
+        editor.backspace();
+        assert_eq!(editor.text(), "xy");
+        assert_eq!(editor.cursor(), 1);
+
+        editor.home();
+        editor.right();
+        editor.delete();
+        assert_eq!(editor.text(), "x");
+    }
+
+    #[test]
+    fn test_delete() {
+        let mut editor = with_text("foo bar  baz");
+        editor.delete_word();
+        assert_eq!(editor.text(), "foo bar  ");
+        editor.delete_word();
+        assert_eq!(editor.text(), "foo ");
+
+        let mut editor = with_text("abcdef");
+        editor.left();
+        editor.left();
+        editor.delete_to_end();
+        assert_eq!(editor.text(), "abcd");
+        editor.left();
+        editor.delete_to_start();
+        assert_eq!((editor.text(), editor.cursor()), ("d", 0));
+    }
+}
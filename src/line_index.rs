@@ -0,0 +1,522 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// The offset of every `CHECKPOINT_INTERVAL`th line is stored.
+pub const CHECKPOINT_INTERVAL: u64 = 1000;
+
+/// How much the background thread reads at a time.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// How long the background thread sleeps after catching up with the end of
+/// an input that may still grow.
+pub const IDLE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Where a line starts, as far as the index knows.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LinePosition {
+    At(u64),
+    /// The input has fewer lines, and won't get more.
+    PastEnd,
+    /// The index hasn't got to the line yet.
+    Unknown,
+}
+
+/// Positional reads that don't move any shared cursor, so an input can be
+/// scanned on a separate thread while the pager seeks around in it.
+pub trait ReadAt {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize>;
+
+    /// True once no more data will be appended.
+    fn is_eof(&self) -> bool {
+        false
+    }
+}
+
+/// A file is read as it is. One that is being followed is read through a
+/// `FileSource`, which waits for more.
+impl ReadAt for std::fs::File {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        use std::os::unix::fs::FileExt;
+        FileExt::read_at(self, buf, offset)
+    }
+
+    fn is_eof(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug)]
+struct IndexState {
+    /// `checkpoints[i]` is the offset of line `i * interval`, zero indexed.
+    checkpoints: Vec<u64>,
+    /// Newlines seen so far.
+    newlines: u64,
+    /// Bytes scanned so far.
+    scanned: u64,
+    /// The scan has reached the current end of the input.
+    caught_up: bool,
+    /// A background thread is scanning, or waiting for more data.
+    running: bool,
+    /// Why reading the input failed, which stopped the scan.
+    error: Option<io::Error>,
+}
+
+/// A sparse line number to byte offset index, built incrementally by a
+/// background thread once `start` is called. Lookups only answer for the
+/// part of the input that has been scanned so far.
+///
+/// Line numbers are one indexed.
+pub struct LineIndex {
+    source: Arc<dyn ReadAt + Send + Sync>,
+    state: Arc<Mutex<IndexState>>,
+    interval: u64,
+    stop: Arc<AtomicBool>,
+}
+
+impl LineIndex {
+    pub fn new(source: Arc<dyn ReadAt + Send + Sync>) -> LineIndex {
+        LineIndex::with_interval(source, CHECKPOINT_INTERVAL)
+    }
+
+    fn with_interval(
+        source: Arc<dyn ReadAt + Send + Sync>,
+        interval: u64,
+    ) -> LineIndex {
+        let state = Arc::new(Mutex::new(IndexState {
+            checkpoints: vec![0],
+            newlines: 0,
+            scanned: 0,
+            caught_up: false,
+            running: false,
+            error: None,
+        }));
+
+        LineIndex {
+            source,
+            state,
+            interval,
+            stop: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Scans the input in the background, unless that is already being
+    /// done. Once the scan has caught up with an input that isn't growing
+    /// the thread stops, and is only started again if data has been
+    /// appended since.
+    pub fn start(&self) {
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.running || state.error.is_some() {
+                return;
+            }
+            let mut buf = [0; 1];
+            if state.caught_up
+                && self.source.read_at(&mut buf, state.scanned).ok() == Some(0)
+            {
+                return;
+            }
+            state.running = true;
+        }
+
+        let source = self.source.clone();
+        let state = self.state.clone();
+        let stop = self.stop.clone();
+        let interval = self.interval;
+        thread::spawn(move || {
+            build_index(&*source, &state, &stop, interval);
+            state.lock().unwrap().running = false;
+        });
+    }
+
+    /// The line containing `offset`, if the index has got that far.
+    pub fn line_of_offset(&self, offset: u64) -> Option<u64> {
+        let (checkpoint, start) = {
+            let state = self.state.lock().unwrap();
+            if offset > state.scanned {
+                return None;
+            }
+            let i = match state.checkpoints.binary_search(&offset) {
+                Ok(i) => i,
+                Err(i) => i - 1,
+            };
+            (i as u64, state.checkpoints[i])
+        };
+
+        let newlines = self.count_newlines(start, offset).ok()?;
+        Some(checkpoint * self.interval + newlines + 1)
+    }
+
+    /// The offset where `line` starts, if the index has got that far.
+    pub fn offset_of_line(&self, line: u64) -> Option<u64> {
+        if line == 0 {
+            return None;
+        }
+        let line = line - 1;
+
+        let (start, end) = {
+            let state = self.state.lock().unwrap();
+            if line > state.newlines {
+                return None;
+            }
+            let end = if state.caught_up {
+                Some(state.scanned)
+            } else {
+                None
+            };
+            (state.checkpoints[(line / self.interval) as usize], end)
+        };
+
+        let offset = self.skip_newlines(start, line % self.interval).ok()?;
+        // An input ending in a newline has no line after it, as in
+        // `line_count`.
+        match end {
+            Some(end) if offset >= end => None,
+            _ => Some(offset),
+        }
+    }
+
+    /// Like `offset_of_line`, but also tells a line past the end of the
+    /// input from one that isn't indexed yet. The error is the one reading
+    /// the input failed with, if it did.
+    pub fn find_line(&self, line: u64) -> io::Result<LinePosition> {
+        // Check before looking up, so a line indexed in between isn't
+        // mistaken for being past the end.
+        let caught_up = self.is_caught_up() && self.source.is_eof();
+        if let Some(offset) = self.offset_of_line(line) {
+            return Ok(LinePosition::At(offset));
+        }
+        if let Some(error) = self.error() {
+            return Err(error);
+        }
+        Ok(if caught_up {
+            LinePosition::PastEnd
+        } else {
+            LinePosition::Unknown
+        })
+    }
+
+    /// The number of lines in the input, when all of it has been indexed.
+    pub fn line_count(&self) -> Option<u64> {
+        let state = self.state.lock().unwrap();
+        if !state.caught_up {
+            return None;
+        }
+        Some(
+            if state.scanned == 0 || self.ends_with_newline(state.scanned) {
+                state.newlines
+            } else {
+                state.newlines + 1
+            },
+        )
+    }
+
+    /// True when everything currently in the input has been indexed. Goes
+    /// back to false if more data is appended.
+    pub fn is_caught_up(&self) -> bool {
+        self.state.lock().unwrap().caught_up
+    }
+
+    /// Why the scan stopped before the end of the input, if it did.
+    pub fn error(&self) -> Option<io::Error> {
+        let state = self.state.lock().unwrap();
+        let error = state.error.as_ref()?;
+        Some(io::Error::new(error.kind(), error.to_string()))
+    }
+
+    fn ends_with_newline(&self, size: u64) -> bool {
+        let mut buf = [0; 1];
+        size > 0
+            && self.source.read_at(&mut buf, size - 1).unwrap_or(0) == 1
+            && buf[0] == b'\n'
+    }
+
+    fn count_newlines(&self, mut start: u64, end: u64) -> io::Result<u64> {
+        let mut buf = vec![0; CHUNK_SIZE];
+        let mut newlines = 0;
+        while start < end {
+            let len = std::cmp::min(CHUNK_SIZE as u64, end - start) as usize;
+            let n = self.source.read_at(&mut buf[..len], start)?;
+            if n == 0 {
+                break;
+            }
+            newlines += buf[..n].iter().filter(|&&b| b == b'\n').count();
+            start += n as u64;
+        }
+        Ok(newlines as u64)
+    }
+
+    /// The offset just after the `n`th newline from `start`.
+    fn skip_newlines(&self, mut start: u64, mut n: u64) -> io::Result<u64> {
+        let mut buf = vec![0; CHUNK_SIZE];
+        while n > 0 {
+            let bytes_read = self.source.read_at(&mut buf, start)?;
+            if bytes_read == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Line is past the end of the input.",
+                ));
+            }
+            for &b in &buf[..bytes_read] {
+                start += 1;
+                if b == b'\n' {
+                    n -= 1;
+                    if n == 0 {
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(start)
+    }
+}
+
+impl Drop for LineIndex {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Scans `source` from where the last scan got to, until it has caught up
+/// with an input that won't grow anymore.
+fn build_index(
+    source: &dyn ReadAt,
+    state: &Mutex<IndexState>,
+    stop: &AtomicBool,
+    interval: u64,
+) {
+    let mut buf = vec![0; CHUNK_SIZE];
+    let (mut scanned, mut newlines) = {
+        let state = state.lock().unwrap();
+        (state.scanned, state.newlines)
+    };
+
+    while !stop.load(Ordering::SeqCst) {
+        // Check before reading, so data appended after the last read is
+        // never missed.
+        let eof = source.is_eof();
+
+        let bytes_read = match source.read_at(&mut buf, scanned) {
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                state.lock().unwrap().error = Some(e);
+                return;
+            }
+        };
+
+        if bytes_read == 0 {
+            state.lock().unwrap().caught_up = true;
+            if eof {
+                return;
+            }
+            thread::sleep(IDLE_INTERVAL);
+            continue;
+        }
+
+        let mut checkpoints = Vec::new();
+        for (i, &b) in buf[..bytes_read].iter().enumerate() {
+            if b == b'\n' {
+                newlines += 1;
+                if newlines % interval == 0 {
+                    checkpoints.push(scanned + i as u64 + 1);
+                }
+            }
+        }
+        scanned += bytes_read as u64;
+
+        let mut state = state.lock().unwrap();
+        state.checkpoints.extend(checkpoints);
+        state.newlines = newlines;
+        state.scanned = scanned;
+        state.caught_up = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use reader::StdinBuffer;
+
+    /// Like a file that isn't followed: it's read as it is, but it may
+    /// have been appended to when it's read again.
+    struct Bytes(Mutex<Vec<u8>>);
+
+    impl Bytes {
+        fn new(data: &[u8]) -> Arc<Bytes> {
+            Arc::new(Bytes(Mutex::new(data.to_vec())))
+        }
+    }
+
+    impl ReadAt for Bytes {
+        fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+            let data = self.0.lock().unwrap();
+            let start = std::cmp::min(offset as usize, data.len());
+            let n = std::cmp::min(buf.len(), data.len() - start);
+            buf[..n].copy_from_slice(&data[start..start + n]);
+            Ok(n)
+        }
+
+        fn is_eof(&self) -> bool {
+            true
+        }
+    }
+
+    struct Failing;
+
+    impl ReadAt for Failing {
+        fn read_at(&self, _buf: &mut [u8], _offset: u64) -> io::Result<usize> {
+            Err(io::Error::from_raw_os_error(5))
+        }
+    }
+
+    fn index(s: &str, interval: u64) -> LineIndex {
+        let index =
+            LineIndex::with_interval(Bytes::new(s.as_bytes()), interval);
+        index.start();
+        wait_until_stopped(&index);
+        index
+    }
+
+    fn is_running(index: &LineIndex) -> bool {
+        index.state.lock().unwrap().running
+    }
+
+    fn wait_until_stopped(index: &LineIndex) {
+        while is_running(index) {
+            thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn test_offset_of_line() {
+        let index = index("a\nbb\n\nccc\nd", 2);
+        assert_eq!(index.offset_of_line(0), None);
+        assert_eq!(index.offset_of_line(1), Some(0));
+        assert_eq!(index.offset_of_line(2), Some(2));
+        assert_eq!(index.offset_of_line(3), Some(5));
+        assert_eq!(index.offset_of_line(4), Some(6));
+        assert_eq!(index.offset_of_line(5), Some(10));
+        assert_eq!(index.offset_of_line(6), None);
+    }
+
+    #[test]
+    fn test_find_line() {
+        let lines = index("a\nb\nc\n", 2);
+        assert_eq!(lines.find_line(3).unwrap(), LinePosition::At(4));
+        assert_eq!(lines.find_line(4).unwrap(), LinePosition::PastEnd);
+        assert_eq!(lines.find_line(5).unwrap(), LinePosition::PastEnd);
+
+        let lines = index("a\nb", 2);
+        assert_eq!(lines.find_line(2).unwrap(), LinePosition::At(2));
+        assert_eq!(lines.find_line(3).unwrap(), LinePosition::PastEnd);
+
+        // Nothing is known before the index is started.
+        let lines = LineIndex::with_interval(Bytes::new(b"a\n"), 2);
+        assert_eq!(lines.find_line(2).unwrap(), LinePosition::Unknown);
+
+        // More lines may still come.
+        let buffer = Arc::new(StdinBuffer::new());
+        buffer.append(b"a\n");
+        let lines = LineIndex::with_interval(buffer.clone(), 2);
+        lines.start();
+        while !lines.is_caught_up() {
+            thread::yield_now();
+        }
+        assert_eq!(lines.find_line(2).unwrap(), LinePosition::Unknown);
+        buffer.close();
+        wait_until_stopped(&lines);
+        assert_eq!(lines.find_line(2).unwrap(), LinePosition::PastEnd);
+    }
+
+    #[test]
+    fn test_start() {
+        let bytes = Bytes::new(b"a\nb\n");
+        let index = LineIndex::with_interval(bytes.clone(), 2);
+        // Nothing is read before the index is started.
+        assert_eq!(index.line_count(), None);
+
+        index.start();
+        wait_until_stopped(&index);
+        assert_eq!(index.line_count(), Some(2));
+
+        // Data appended after the scan stopped is read when it's started
+        // again.
+        bytes.0.lock().unwrap().extend_from_slice(b"c\n");
+        index.start();
+        wait_until_stopped(&index);
+        assert_eq!(index.line_count(), Some(3));
+        assert_eq!(index.offset_of_line(3), Some(4));
+    }
+
+    #[test]
+    fn test_waits_for_growing_input() {
+        let buffer = Arc::new(StdinBuffer::new());
+        buffer.append(b"a\n");
+        let index = LineIndex::with_interval(buffer.clone(), 2);
+        index.start();
+        while !index.is_caught_up() {
+            thread::yield_now();
+        }
+        assert!(is_running(&index));
+
+        buffer.append(b"b\n");
+        while index.offset_of_line(2).is_none() {
+            thread::yield_now();
+        }
+        buffer.close();
+        wait_until_stopped(&index);
+        assert_eq!(index.line_count(), Some(2));
+    }
+
+    #[test]
+    fn test_read_error() {
+        let index = LineIndex::new(Arc::new(Failing));
+        index.start();
+        wait_until_stopped(&index);
+        let error = index.find_line(2).unwrap_err();
+        assert!(error.to_string().starts_with("Input/output error"));
+        assert_eq!(index.line_count(), None);
+    }
+
+    #[test]
+    fn test_line_of_offset() {
+        let index = index("a\nbb\n\nccc\nd", 2);
+        assert_eq!(index.line_of_offset(0), Some(1));
+        assert_eq!(index.line_of_offset(1), Some(1));
+        assert_eq!(index.line_of_offset(2), Some(2));
+        assert_eq!(index.line_of_offset(5), Some(3));
+        assert_eq!(index.line_of_offset(9), Some(4));
+        assert_eq!(index.line_of_offset(10), Some(5));
+        assert_eq!(index.line_of_offset(11), Some(5));
+        assert_eq!(index.line_of_offset(12), None);
+    }
+
+    #[test]
+    fn test_line_count() {
+        assert_eq!(index("", 2).line_count(), Some(0));
+        assert_eq!(index("a", 2).line_count(), Some(1));
+        assert_eq!(index("a\n", 2).line_count(), Some(1));
+        assert_eq!(index("a\nb", 2).line_count(), Some(2));
+        assert_eq!(index("\n\n\n\n\n", 2).line_count(), Some(5));
+    }
+
+    #[test]
+    fn test_round_trip_file() {
+        use std::fs::File;
+
+        let file = File::open("tests/resources/bible_short.txt").unwrap();
+        let index = LineIndex::with_interval(Arc::new(file), 2);
+        index.start();
+        wait_until_stopped(&index);
+
+        assert_eq!(index.line_count(), Some(5));
+        for line in 1..6 {
+            let offset = index.offset_of_line(line).unwrap();
+            assert_eq!(index.line_of_offset(offset), Some(line));
+        }
+    }
+}
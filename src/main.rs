@@ -3,47 +3,119 @@ extern crate grep;
 extern crate libc;
 extern crate termion;
 extern crate unicode_segmentation;
+extern crate unicode_width;
 
 use termion::raw::IntoRawMode;
 
+use input::Command;
+
 use std::io::stdout;
 use std::result::Result;
 
+mod ansi;
 mod app;
 mod controller;
 mod error;
+mod filter;
+mod highlight;
+mod history;
 mod input;
+mod interrupt;
+mod line_editor;
+mod line_index;
+mod message;
 mod printer;
 mod reader;
 mod searcher;
 mod standard;
+mod status;
 mod utf8_validation;
 mod util;
 
 fn main() {
     let app = app::App::new();
 
-    let input_reader = app.input_reader();
+    let input_reader = match app.input_reader() {
+        Some(input_reader) => input_reader,
+        None => std::process::exit(1),
+    };
 
-    if let Err(_) = run(input_reader, !app.matches.is_present("no-wrap")) {
+    if run(&app, input_reader).is_err() {
         std::process::exit(1);
     }
 }
 
-fn run(input_reader: reader::InputReader, wrap_lines: bool) -> Result<(), ()> {
+fn run(app: &app::App, input_reader: reader::InputReader) -> Result<(), ()> {
     let mut printer = printer::Printer::new(stdout().into_raw_mode().unwrap());
 
+    let wrap_lines = !app.matches.is_present("no-wrap");
     let mut controller = controller::Controller::new(input_reader, wrap_lines);
+    controller.set_line_numbers(app.matches.is_present("line-numbers"));
+    controller.set_ansi(app.matches.is_present("raw-control-chars"));
+    controller.set_wrap_search(app.matches.is_present("wrap-search"));
+    if app.matches.is_present("IGNORE-CASE") {
+        controller.set_case_mode(searcher::CaseMode::Insensitive);
+    } else if app.matches.is_present("ignore-case") {
+        controller.set_case_mode(searcher::CaseMode::Smart);
+    }
+    if let Some(path) = history::history_path() {
+        controller.set_history_file(path);
+    }
+    if let Some(prompt) = app.matches.value_of("prompt") {
+        controller.set_prompt(prompt);
+    }
+    // Ctrl-C is only read as a key once the events are, until then it
+    // mustn't kill the pager either.
+    interrupt::catch_sigint();
+    let events = input::Events::new();
+
+    if let Some(line) = app.start_line() {
+        if let Err(e) = controller.update_command(Command::JumpLine(line)) {
+            controller.show_error(&e.context("go to the start line"));
+        }
+    }
+    if app.matches.is_present("follow") {
+        if let Err(e) = controller.follow() {
+            controller.show_error(&e.context("follow the input"));
+        }
+    }
+
+    let mut redraw = true;
 
     loop {
-        let _ = printer.render(&mut controller);
+        if redraw {
+            let _ = printer.render(&mut controller);
+        }
 
-        // Blocks, waiting for input.
-        // Screen is not redrawn until input is registered.
-        let input = input::get_input();
+        // Blocks, waiting for input or a resize. Only times out when the
+        // controller has something to poll for, e.g. a file being followed.
+        let event = events.next(controller.poll_interval());
+        let interrupted = controller.check_interrupt();
+        let input = match event {
+            input::InputEvent::Key(input) => input,
+            input::InputEvent::Resize => {
+                if let Err(e) = controller.resize() {
+                    controller.show_error(&e.context("resize the page"));
+                }
+                redraw = true;
+                continue;
+            }
+            input::InputEvent::Tick => {
+                redraw = match controller.poll() {
+                    Ok(changed) => changed || interrupted,
+                    Err(e) => {
+                        controller.show_error(&e.context("read the input"));
+                        true
+                    }
+                };
+                continue;
+            }
+            input::InputEvent::Closed => break,
+        };
+        redraw = true;
 
         if let Err(e) = controller.update(&input) {
-            eprintln!("Error in controller.update: {}", e);
+            controller.show_error(&e);
         }
 
         if controller.quit {
@@ -0,0 +1,67 @@
+use std::collections::VecDeque;
+
+/// How a message is shown.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MessageKind {
+    Info,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub kind: MessageKind,
+    pub text: String,
+}
+
+/// Messages waiting to be shown in the command line row, oldest first.
+/// Each is shown until the next key press, then the next one is.
+#[derive(Debug, Default)]
+pub struct MessageQueue {
+    messages: VecDeque<Message>,
+}
+
+impl MessageQueue {
+    pub fn new() -> MessageQueue {
+        MessageQueue::default()
+    }
+
+    /// Queues `text`, unless it's the same as the last message queued, e.g.
+    /// an error that happens every time the input is polled.
+    pub fn push(&mut self, kind: MessageKind, text: String) {
+        let message = Message { kind, text };
+        if self.messages.back() != Some(&message) {
+            self.messages.push_back(message);
+        }
+    }
+
+    /// The message being shown.
+    pub fn current(&self) -> Option<&Message> {
+        self.messages.front()
+    }
+
+    /// Drops the message being shown, to show the next one.
+    pub fn dismiss(&mut self) {
+        self.messages.pop_front();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queue() {
+        let mut queue = MessageQueue::new();
+        assert_eq!(queue.current(), None);
+
+        queue.push(MessageKind::Error, String::from("a.txt: Is a directory"));
+        queue.push(MessageKind::Error, String::from("a.txt: Is a directory"));
+        queue.push(MessageKind::Info, String::from("No more matches"));
+        assert_eq!(queue.current().unwrap().kind, MessageKind::Error);
+
+        queue.dismiss();
+        assert_eq!(queue.current().unwrap().text, "No more matches");
+        queue.dismiss();
+        assert_eq!(queue.current(), None);
+    }
+}
@@ -1,22 +1,32 @@
 use termion::color;
 use termion::screen::AlternateScreen;
-use unicode_segmentation::UnicodeSegmentation;
+use termion::style;
 
 use std::io::Write;
 use std::str;
 
 use grep::matcher::Match;
 
+use ansi;
 use controller::Controller;
+use message::MessageKind;
 use util;
 
+/// Why a grapheme is highlighted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HighlightKind {
+    Search,
+    /// A pinned pattern, in its ANSI color.
+    Pinned(u8),
+}
+
 struct ColoredString {
     string: String,
-    color: bool,
+    color: Option<HighlightKind>,
 }
 
 impl ColoredString {
-    pub fn new(s: &str, color: bool) -> ColoredString {
+    pub fn new(s: &str, color: Option<HighlightKind>) -> ColoredString {
         ColoredString {
             string: s.to_string(),
             color: color,
@@ -24,6 +34,31 @@ impl ColoredString {
     }
 }
 
+/// Where the line numbers of a page start.
+pub struct Gutter {
+    /// The line at the top of the page, if it is known yet.
+    pub top_line: Option<u64>,
+    /// The top of the page is in the middle of `top_line`, i.e., the first
+    /// row is a wrapped continuation.
+    pub continued: bool,
+    /// The number of every line starting on the page, when they don't
+    /// follow each other, e.g. when filtering.
+    pub lines: Option<Vec<Option<u64>>>,
+}
+
+impl Gutter {
+    /// The number of the `n`th line starting on the page, zero indexed.
+    fn line(&self, n: usize) -> Option<u64> {
+        match &self.lines {
+            Some(lines) => lines.get(n).cloned().and_then(|line| line),
+            None => {
+                let first = self.top_line? + self.continued as u64;
+                Some(first + n as u64)
+            }
+        }
+    }
+}
+
 pub struct Printer<W: Write> {
     pub out: AlternateScreen<W>,
     output_buffer: Vec<ColoredString>,
@@ -40,51 +75,148 @@ impl<W: Write> Printer<W> {
     pub fn render(&mut self, controller: &mut Controller) -> Result<(), ()> {
         self.clear_screen();
 
-        let page = controller.page();
-        let matches = &controller.matches;
+        if let Some(overlay) = controller.overlay() {
+            let mut overlay = overlay.into_bytes();
+            overlay.push(b'\n');
+            let highlights = vec![None; overlay.len()];
+            self.print_page(&overlay, highlights, true, None, 0, false)?;
+        } else {
+            self.print_content(controller)?;
+        }
         let command_line_text = controller.command_line_text().clone();
-        let wrap = controller.is_wrap();
-
-        self.print_page(&page.1, search_offsets(page.0, matches), wrap)?;
-        self.print_command_line(command_line_text);
+        let cursor = controller.command_line_cursor();
+        let kind = controller.message_kind();
+        self.print_command_line(command_line_text, cursor, kind);
         self.flush();
 
         Ok(())
     }
 
+    /// Prints the page the controller is at.
+    fn print_content(&mut self, controller: &mut Controller) -> Result<(), ()> {
+        let page = controller.page();
+        let wrap = controller.is_wrap();
+        let gutter = controller.gutter(&page);
+        let h_offset = controller.h_offset();
+        let ansi = controller.is_ansi();
+        let matches = controller.page_matches();
+        let pinned = controller.page_highlights();
+
+        self.print_page(
+            &page.1,
+            highlights(page.0, page.1.len(), &matches, &pinned),
+            wrap,
+            gutter,
+            h_offset,
+            ansi,
+        )
+    }
+
     pub fn print_page(
         &mut self,
         page: &Vec<u8>,
-        search_offsets: Vec<u64>,
+        highlights: Vec<Option<HighlightKind>>,
         wrap: bool,
+        gutter: Option<Gutter>,
+        h_offset: usize,
+        ansi: bool,
     ) -> Result<(), ()> {
         let mut screen_line_number: u16 = 1;
         let (screen_width, screen_height) = util::screen_width_height();
-
-        self.output_buffer.push(ColoredString::new("", false));
+        let screen_width = util::text_width(screen_width, gutter.is_some());
+
+        // Lines started on the page so far.
+        let mut lines_started = 0;
+
+        self.output_buffer.push(ColoredString::new("", None));
+        // The gutter of a new line is pushed along with its first grapheme,
+        // so there is no number for the empty line after the last newline.
+        let mut line_starts = match &gutter {
+            Some(gutter) if gutter.continued => {
+                self.push_gutter(None);
+                false
+            }
+            Some(_) => true,
+            None => false,
+        };
 
         unsafe {
             let page_string = str::from_utf8_unchecked(&page[..]);
 
             write(&mut self.out, &termion::cursor::Goto(1, 1));
 
-            let mut iter =
-                UnicodeSegmentation::grapheme_indices(&page_string[..], true);
+            let mut iter = ansi::graphemes(page_string, ansi).peekable();
             let mut grapheme_count = 0;
+            // Graphemes into the current line, including those scrolled past.
+            let mut column = 0;
+            // Colors from the input. Every row starts without them, so they
+            // are turned back on before the next visible grapheme.
+            let mut style = ansi::Style::new();
+            let mut restyle = false;
 
             'outer: while let Some((index, grapheme)) = iter.next() {
                 if screen_line_number >= screen_height - 1 {
                     break;
                 }
 
-                if grapheme_count >= screen_width as usize {
+                if ansi::is_escape(grapheme) {
+                    // Other sequences could move the cursor, drop them.
+                    if ansi::is_style(grapheme) {
+                        style.apply(grapheme);
+                        restyle = true;
+                    }
+                    continue;
+                }
+
+                if line_starts {
+                    let number =
+                        gutter.as_ref().and_then(|g| g.line(lines_started));
+                    self.push_gutter(number);
+                    lines_started += 1;
+                    line_starts = false;
+                }
+
+                if !wrap && !util::is_newline(grapheme) {
+                    column += 1;
+                    if column <= h_offset {
+                        continue;
+                    }
+
+                    // Show that the line continues off screen in the last
+                    // column.
+                    let continues = match iter.peek() {
+                        Some((_, next)) => !util::is_newline(next),
+                        None => false,
+                    };
+                    if grapheme_count + 1 == screen_width as usize && continues
+                    {
+                        grapheme_count += 1;
+                        self.push_str(">", None);
+                        continue;
+                    }
+                }
+
+                // Without wrapping, a newline right at the edge just ends the
+                // line.
+                if grapheme_count >= screen_width as usize
+                    && (wrap || !util::is_newline(grapheme))
+                {
                     grapheme_count = 0;
                     screen_line_number += 1;
+                    self.push_row_end(ansi);
+                    restyle = true;
                     if wrap {
-                        self.push_newline();
+                        if gutter.is_some() {
+                            self.push_gutter(None);
+                        }
                     } else {
-                        while let Some((index, grapheme)) = iter.next() {
+                        for (_index, grapheme) in iter.by_ref() {
+                            if ansi::is_style(grapheme) {
+                                style.apply(grapheme);
+                            }
                             if util::is_newline(grapheme) {
+                                column = 0;
+                                line_starts = gutter.is_some();
                                 continue 'outer;
                             }
                         }
@@ -93,17 +225,29 @@ impl<W: Write> Printer<W> {
 
                 if util::is_newline(grapheme) {
                     grapheme_count = 0;
+                    column = 0;
                     screen_line_number += 1;
-                    self.push_newline();
+                    self.push_row_end(ansi);
+                    restyle = true;
+                    line_starts = gutter.is_some();
                 } else {
                     grapheme_count += 1;
-                    self.push_str(
-                        &grapheme,
-                        search_offsets.contains(&(index as u64)),
-                    );
+                    let highlight = highlights.get(index).and_then(|&h| h);
+                    if highlight.is_some() {
+                        // The highlight turns the colors off when it ends.
+                        restyle = true;
+                    } else if ansi && restyle {
+                        self.push_str(ansi::RESET, None);
+                        self.push_str(style.active(), None);
+                        restyle = false;
+                    }
+                    self.push_str(grapheme, highlight);
                 }
             }
 
+            if ansi {
+                self.push_str(ansi::RESET, None);
+            }
             for _ in screen_line_number..(screen_height - 1) {
                 self.push_tilde_newline();
             }
@@ -115,16 +259,49 @@ impl<W: Write> Printer<W> {
         Ok(())
     }
 
-    fn print_command_line(&mut self, command_line_text: String) {
-        let (_screen_width, screen_height) = util::screen_width_height();
+    /// Prints `command_line_text` with the cursor at byte offset `cursor`,
+    /// or at the end. Messages are shown in bold, errors also in red.
+    fn print_command_line(
+        &mut self,
+        command_line_text: String,
+        cursor: Option<usize>,
+        kind: Option<MessageKind>,
+    ) {
+        let (screen_width, screen_height) = util::screen_width_height();
+        // Leave room for the cursor, a wrapped line would scroll the page.
+        let ((start, end), column) = match cursor {
+            Some(cursor) => util::cursor_window(
+                &command_line_text,
+                cursor,
+                screen_width as usize,
+            ),
+            // Cut off the end of a long status line, not the start.
+            None => {
+                let (shown, _) = util::cursor_window(
+                    &command_line_text,
+                    0,
+                    screen_width as usize,
+                );
+                let text = &command_line_text[shown.0..shown.1];
+                (shown, util::display_width(text))
+            }
+        };
         write(&mut self.out, &"\n\r");
-        write(&mut self.out, &command_line_text);
+        match kind {
+            Some(MessageKind::Error) => {
+                write(&mut self.out, &color::Fg(color::Red));
+                write(&mut self.out, &style::Bold);
+            }
+            Some(MessageKind::Info) => write(&mut self.out, &style::Bold),
+            None => (),
+        }
+        write(&mut self.out, &&command_line_text[start..end]);
+        if kind.is_some() {
+            write(&mut self.out, &style::Reset);
+        }
         write(
             &mut self.out,
-            &termion::cursor::Goto(
-                (util::grapheme_count(&command_line_text[..]) + 1) as u16,
-                screen_height + 1,
-            ),
+            &termion::cursor::Goto(column as u16 + 1, screen_height + 1),
         );
     }
 
@@ -138,23 +315,45 @@ impl<W: Write> Printer<W> {
 
     fn write_output_buffer(&mut self) {
         for colored_string in &self.output_buffer {
-            if colored_string.color {
-                write_higlight(&mut self.out, &colored_string.string);
-            } else {
-                write(&mut self.out, &colored_string.string);
+            match colored_string.color {
+                Some(highlight) => write_higlight(
+                    &mut self.out,
+                    &colored_string.string,
+                    highlight,
+                ),
+                None => write(&mut self.out, &colored_string.string),
             }
         }
     }
 
+    /// Pushes a gutter with `number`, or a blank one.
+    fn push_gutter(&mut self, number: Option<u64>) {
+        let width = util::LINE_NUMBER_WIDTH as usize - 1;
+        let text = match number {
+            Some(n) => format!("{:>width$} ", n, width = width),
+            None => format!("{:>width$} ", "", width = width),
+        };
+        self.push_str(&text, None);
+    }
+
     fn push_tilde_newline(&mut self) {
-        self.push_str(&"~\n\r", false);
+        self.push_str("~\n\r", None);
     }
 
     fn push_newline(&mut self) {
-        self.push_str(&"\n\r", false);
+        self.push_str("\n\r", None);
     }
 
-    fn push_str(&mut self, grapheme: &str, colored: bool) {
+    /// Ends a row, without letting colors from the input spill into the
+    /// gutter or the rest of the row.
+    fn push_row_end(&mut self, ansi: bool) {
+        if ansi {
+            self.push_str(ansi::RESET, None);
+        }
+        self.push_newline();
+    }
+
+    fn push_str(&mut self, grapheme: &str, colored: Option<HighlightKind>) {
         let last_index = self.output_buffer.len() - 1;
 
         if self.output_buffer[last_index].color == colored {
@@ -169,12 +368,17 @@ impl<W: Write> Printer<W> {
 fn write_higlight<D: std::fmt::Display, W: Write>(
     out: &mut AlternateScreen<W>,
     text: &D,
+    highlight: HighlightKind,
 ) {
+    let background = match highlight {
+        HighlightKind::Search => color::AnsiValue(7),
+        HighlightKind::Pinned(n) => color::AnsiValue(n),
+    };
     let _ = write!(
         out,
         "{}{}{}",
         color::Fg(color::Black),
-        color::Bg(color::White),
+        color::Bg(background),
         text
     );
     let _ = write!(
@@ -192,21 +396,29 @@ fn write<D: std::fmt::Display, W: Write>(
     let _ = write!(out, "{}", text);
 }
 
-fn search_offsets(start: u64, matches: &Vec<(u64, Match)>) -> Vec<u64> {
-    let mut res = Vec::new();
-    for (offset, mat) in matches {
-        if (*offset + mat.start() as u64) < start {
-            continue;
-        }
-        let s = *offset + mat.start() as u64 - start;
-        let e = *offset + mat.end() as u64 - start;
-        for i in s..e {
-            res.push(i);
-        }
-        // Are X mathces enough?
-        if res.len() > 3000 {
-            break;
+/// How every byte of a page starting at `start` is highlighted. The
+/// search is drawn over pinned patterns, and later pins over earlier ones.
+fn highlights(
+    start: u64,
+    len: usize,
+    matches: &[(u64, Match)],
+    pinned: &[(u8, Vec<(u64, Match)>)],
+) -> Vec<Option<HighlightKind>> {
+    let mut res = vec![None; len];
+    let mut mark = |matches: &[(u64, Match)], highlight: HighlightKind| {
+        for (offset, mat) in matches {
+            let s = (*offset + mat.start() as u64).saturating_sub(start);
+            let e = (*offset + mat.end() as u64).saturating_sub(start);
+            let e = std::cmp::min(e as usize, len);
+            let s = std::cmp::min(s as usize, e);
+            for h in &mut res[s..e] {
+                *h = Some(highlight);
+            }
         }
+    };
+    for (color, matches) in pinned {
+        mark(matches, HighlightKind::Pinned(*color));
     }
+    mark(matches, HighlightKind::Search);
     res
 }
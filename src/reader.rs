@@ -1,33 +1,106 @@
 use std::fs::File;
-use std::io::{self, Cursor, Read, Seek, SeekFrom};
+use std::io::{self, Read, Seek, SeekFrom};
 use std::str;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
 
-use grep::matcher::Match;
 use grep::regex::RegexMatcher;
 
-use app::InputType;
+use app::{self, InputFile, InputType};
 use error::{Error, Result};
-use searcher;
-use standard::StandardSink;
+use filter::Filter;
+use line_index::{LineIndex, LinePosition, ReadAt};
+use searcher::BackgroundSearch;
 use utf8_validation;
 use util;
 
 pub trait Search {
-    fn search(&mut self, matches: &mut Vec<(u64, Match)>, pattern: &str);
+    /// Starts searching the current input on a separate thread, from
+    /// `from` on, which is the start of a line. `None` if the input can't
+    /// be read independently of the pager.
+    fn search(
+        &self,
+        matcher: RegexMatcher,
+        from: u64,
+    ) -> Option<BackgroundSearch>;
 }
 
 pub trait FileSwitcher {
     fn next_file(&mut self) -> Result<()>;
+    /// Makes the zero indexed file `index` the current one.
+    fn switch_file(&mut self, index: usize) -> Result<()>;
+    /// The zero indexed position of the current file in the file list.
+    fn file_index(&self) -> usize;
+    fn file_count(&self) -> usize;
+    /// The name of the current file, empty for stdin.
+    fn file_name(&self) -> &str;
+    /// The names of all files, in order.
+    fn file_names(&self) -> Vec<String>;
+    /// Why the current file couldn't be opened, `None` if it was.
+    fn file_error(&self) -> Option<&Error>;
+    /// Adds the file at `path` after the last one, and makes it the current
+    /// one.
+    fn open_file(&mut self, path: &str) -> Result<()>;
+    /// Takes the current file out of the list, the one after it becomes
+    /// the current one. The last file can't be closed.
+    fn close_file(&mut self) -> Result<()>;
+}
+
+pub trait LineLookup {
+    /// The one indexed line containing `offset`, if it is known yet.
+    fn line_of_offset(&self, offset: u64) -> Option<u64>;
+    /// The offset where the one indexed `line` starts, if it is known yet.
+    fn offset_of_line(&self, line: u64) -> Option<u64>;
+    /// Where `line` starts, or that it hasn't been indexed yet.
+    fn find_line(&self, line: u64) -> Result<LinePosition>;
+    /// The total number of lines, once all of the input has been indexed.
+    fn line_count(&self) -> Option<u64>;
+}
+
+pub trait Filtering {
+    /// Shows only the lines matching `matcher`, or only those that don't
+    /// if `invert` is set. `None` shows every line again.
+    fn filter(&mut self, filter: Option<(RegexMatcher, bool)>);
+    fn is_filtered(&self) -> bool;
+    /// The offset in the input of `offset` in what is shown.
+    fn original_offset(&self, offset: u64) -> u64;
+    /// Where `offset` in the input is shown, `None` if its line is hidden.
+    fn view_offset(&self, offset: u64) -> Option<u64>;
+    /// Where `offset` in the input, or the first line shown after it, is
+    /// shown. `None` if that isn't known yet.
+    fn view_offset_after(&self, offset: u64) -> Option<u64>;
+    /// Looks for more lines to show in what is appended to the input, once
+    /// those there were so far have all been found.
+    fn resume_filter(&mut self);
+}
+
+pub trait Streaming {
+    /// False while more data may still arrive, e.g. from a pipe that hasn't
+    /// been closed yet.
+    fn is_eof(&self) -> bool;
+    /// While following, the background threads reading a file wait for
+    /// data to be appended instead of stopping at its end.
+    fn set_following(&mut self, following: bool);
 }
 
 pub struct BiBufReader<R> {
     inner: R,
     pub wrap: bool,
+    /// Line numbers take up part of the screen width.
+    pub line_numbers: bool,
+    /// ANSI escape sequences take up no screen width.
+    pub ansi: bool,
 }
 
 impl<R: Read + Seek> BiBufReader<R> {
     pub fn new(inner: R, wrap: bool) -> BiBufReader<R> {
-        BiBufReader { inner, wrap: wrap }
+        BiBufReader {
+            inner,
+            wrap,
+            line_numbers: false,
+            ansi: false,
+        }
     }
 
     pub fn jump_offset(&mut self, offset: u64) -> Result<()> {
@@ -51,17 +124,12 @@ impl<R: Read + Seek> BiBufReader<R> {
         unsafe {
             let size = buf.len();
 
-            let (screen_width, _) = util::screen_width_height();
-
             let offset = size as i64
                 - util::nth_last_newline_pos(
                     n + 1,
                     str::from_utf8_unchecked(&buf[..]),
-                    if self.wrap {
-                        Some(screen_width as i32)
-                    } else {
-                        None
-                    },
+                    self.wrap_width(),
+                    self.ansi,
                 ) as i64;
             self.inner.seek(SeekFrom::Current(-(offset as i64)))?;
         }
@@ -70,20 +138,14 @@ impl<R: Read + Seek> BiBufReader<R> {
     }
 
     pub fn down_n_lines(&mut self, n: usize) -> Result<()> {
-        self.pcur_pos()?;
         let (buf, size) = self.make_buf_down()?;
 
-        let (screen_width, _) = util::screen_width_height();
-
         unsafe {
             let newline_offset = util::nth_newline_pos(
                 n,
                 str::from_utf8_unchecked(&buf[..size]),
-                if self.wrap {
-                    Some(screen_width as i32)
-                } else {
-                    None
-                },
+                self.wrap_width(),
+                self.ansi,
             );
             self.inner.seek(SeekFrom::Current(newline_offset as i64))?;
         }
@@ -91,22 +153,56 @@ impl<R: Read + Seek> BiBufReader<R> {
         Ok(())
     }
 
-    pub fn page(&mut self) -> Result<(u64, Vec<u8>)> {
-        let size = self.page_size();
-
-        let mut page_buf = Vec::with_capacity(size);
-        page_buf.resize(size, 0);
+    /// Moves back to the start of the line containing the current position,
+    /// e.g. when the top of the screen is a wrapped continuation.
+    pub fn jump_line_start(&mut self) -> Result<()> {
+        let cur_pos = self.current_offset();
+        let buf = self.make_buf_up()?;
 
-        let bytes_read = match self.inner.read(&mut page_buf[..]) {
-            Err(_) => {
-                panic!("Fatal error in page generation!");
-            }
-            Ok(s) => s as i64,
+        let back = match buf.iter().rposition(|&b| b == b'\n') {
+            Some(pos) => buf.len() - pos - 1,
+            // The whole start of the input is in the buffer.
+            None if buf.len() as u64 == cur_pos => buf.len(),
+            // The line is longer than the buffer, stay put.
+            None => 0,
         };
+        self.inner.seek(SeekFrom::Current(-(back as i64)))?;
 
-        let offset = self.inner.seek(SeekFrom::Current(-bytes_read))?;
+        Ok(())
+    }
 
-        Ok((offset, page_buf[..bytes_read as usize].to_vec()))
+    /// The input from the current position on, as much as fits on the
+    /// screen. When lines aren't wrapped, the `h_offset` columns scrolled
+    /// past on every row are read as well.
+    pub fn page(&mut self, h_offset: usize) -> Result<(u64, Vec<u8>)> {
+        let offset = self.current_offset();
+        let page = self.peek(self.page_len(h_offset))?;
+        Ok((offset, page))
+    }
+
+    /// The next `len` bytes, or fewer at the end of the input, without
+    /// moving the position.
+    pub fn peek(&mut self, len: usize) -> Result<Vec<u8>> {
+        let mut buf = vec![0; len];
+        let mut bytes_read = 0;
+        while bytes_read < len {
+            match self.inner.read(&mut buf[bytes_read..])? {
+                0 => break,
+                n => bytes_read += n,
+            }
+        }
+        self.inner.seek(SeekFrom::Current(-(bytes_read as i64)))?;
+        buf.truncate(bytes_read);
+        Ok(buf)
+    }
+
+    /// The current size of the input. Files that are still being written
+    /// to will report a larger size on later calls.
+    pub fn size(&mut self) -> Result<u64> {
+        let cur_pos = self.inner.stream_position()?;
+        let size = self.inner.seek(SeekFrom::End(0))?;
+        self.inner.seek(SeekFrom::Start(cur_pos))?;
+        Ok(size)
     }
 
     pub fn current_offset(&mut self) -> u64 {
@@ -145,7 +241,7 @@ impl<R: Read + Seek> BiBufReader<R> {
         let bytes_read = self.inner.read(&mut buf)?;
 
         if bytes_read == 0 {
-            return Err(Error::Other);
+            return Err(Error::EndOfInput);
         }
 
         self.inner.seek(SeekFrom::Current(-(bytes_read as i64)))?;
@@ -154,6 +250,16 @@ impl<R: Read + Seek> BiBufReader<R> {
         Ok((buf, bytes_read))
     }
 
+    /// The width lines are wrapped at, `None` when not wrapping.
+    pub fn wrap_width(&self) -> Option<i32> {
+        if self.wrap {
+            let (screen_width, _) = util::screen_width_height();
+            Some(util::text_width(screen_width, self.line_numbers) as i32)
+        } else {
+            None
+        }
+    }
+
     fn search_buf_size(&self) -> usize {
         self.page_size()
     }
@@ -163,11 +269,14 @@ impl<R: Read + Seek> BiBufReader<R> {
         screen_width as usize * screen_height as usize * 4 // 4 is max utf8 char size
     }
 
-    fn pcur_pos(&mut self) -> Result<()> {
-        if let Ok(cur_pos) = self.inner.seek(SeekFrom::Current(0)) {
-            eprintln!("cur_pos: {}", cur_pos);
+    /// Bytes read for a page scrolled `h_offset` columns to the right.
+    fn page_len(&self, h_offset: usize) -> usize {
+        if self.wrap {
+            return self.page_size();
         }
-        Ok(())
+        let (screen_width, screen_height) = util::screen_width_height();
+        let columns = h_offset + screen_width as usize;
+        columns * screen_height as usize * 4
     }
 
     fn seek_percent(&mut self, percent: u64) -> Result<u64> {
@@ -181,15 +290,27 @@ impl<R: Read + Seek> BiBufReader<R> {
     }
 }
 
-impl<R: Search + Seek> Search for BiBufReader<R> {
-    fn search(&mut self, matches: &mut Vec<(u64, Match)>, pattern: &str) {
-        // Searching will seek from current position to end, so first have
-        // to remeber the current position, go to the beginning (we want to
-        // search the entire file), then go back to the original position.
-        let cur_pos = self.inner.seek(SeekFrom::Current(0)).unwrap();
-        let _ = self.inner.seek(SeekFrom::Start(0));
-        self.inner.search(matches, pattern);
-        let _ = self.inner.seek(SeekFrom::Start(cur_pos));
+impl<R: Read + Seek + LineLookup> BiBufReader<R> {
+    /// Puts `line` at the top of the screen, or jumps to the end if there
+    /// are fewer lines. Returns false, staying put, if the line hasn't been
+    /// indexed yet.
+    pub fn jump_line(&mut self, line: u64) -> Result<bool> {
+        match self.inner.find_line(line)? {
+            LinePosition::At(offset) => self.jump_offset(offset)?,
+            LinePosition::PastEnd => self.jump_end()?,
+            LinePosition::Unknown => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+impl<R: Search> Search for BiBufReader<R> {
+    fn search(
+        &self,
+        matcher: RegexMatcher,
+        from: u64,
+    ) -> Option<BackgroundSearch> {
+        self.inner.search(matcher, from)
     }
 }
 
@@ -197,12 +318,111 @@ impl<F: FileSwitcher> FileSwitcher for BiBufReader<F> {
     fn next_file(&mut self) -> Result<()> {
         self.inner.next_file()
     }
+
+    fn switch_file(&mut self, index: usize) -> Result<()> {
+        self.inner.switch_file(index)
+    }
+
+    fn file_index(&self) -> usize {
+        self.inner.file_index()
+    }
+
+    fn file_count(&self) -> usize {
+        self.inner.file_count()
+    }
+
+    fn file_name(&self) -> &str {
+        self.inner.file_name()
+    }
+
+    fn file_names(&self) -> Vec<String> {
+        self.inner.file_names()
+    }
+
+    fn file_error(&self) -> Option<&Error> {
+        self.inner.file_error()
+    }
+
+    fn open_file(&mut self, path: &str) -> Result<()> {
+        self.inner.open_file(path)
+    }
+
+    fn close_file(&mut self) -> Result<()> {
+        self.inner.close_file()
+    }
+}
+
+impl<F: Filtering> Filtering for BiBufReader<F> {
+    fn filter(&mut self, filter: Option<(RegexMatcher, bool)>) {
+        self.inner.filter(filter)
+    }
+
+    fn is_filtered(&self) -> bool {
+        self.inner.is_filtered()
+    }
+
+    fn original_offset(&self, offset: u64) -> u64 {
+        self.inner.original_offset(offset)
+    }
+
+    fn view_offset(&self, offset: u64) -> Option<u64> {
+        self.inner.view_offset(offset)
+    }
+
+    fn view_offset_after(&self, offset: u64) -> Option<u64> {
+        self.inner.view_offset_after(offset)
+    }
+
+    fn resume_filter(&mut self) {
+        self.inner.resume_filter()
+    }
+}
+
+impl<L: LineLookup> LineLookup for BiBufReader<L> {
+    fn line_of_offset(&self, offset: u64) -> Option<u64> {
+        self.inner.line_of_offset(offset)
+    }
+
+    fn offset_of_line(&self, line: u64) -> Option<u64> {
+        self.inner.offset_of_line(line)
+    }
+
+    fn find_line(&self, line: u64) -> Result<LinePosition> {
+        self.inner.find_line(line)
+    }
+
+    fn line_count(&self) -> Option<u64> {
+        self.inner.line_count()
+    }
+}
+
+impl<S: Streaming> Streaming for BiBufReader<S> {
+    fn is_eof(&self) -> bool {
+        self.inner.is_eof()
+    }
+
+    fn set_following(&mut self, following: bool) {
+        self.inner.set_following(following)
+    }
+}
+
+/// How much is read from stdin at a time by the background reader.
+const STDIN_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Data read from stdin so far. Filled by a background thread.
+#[derive(Debug, Default)]
+pub struct StdinBuffer {
+    data: Mutex<Vec<u8>>,
+    eof: AtomicBool,
 }
 
+/// A seekable view of everything read from stdin so far. Reading past the
+/// end of the data returns 0 bytes, like reading the end of a file that is
+/// still being written to.
 #[derive(Debug)]
 pub struct StdinCursor {
-    cursor: Cursor<Vec<u8>>,
-    pub file: File,
+    buffer: Arc<StdinBuffer>,
+    pos: u64,
 }
 
 /// This reader ensures that the position is always at a valid utf-8 code point, i.e., when seeking
@@ -254,8 +474,12 @@ impl<R: Read + Seek> Seek for ValidReader<R> {
 }
 
 impl<S: Search> Search for ValidReader<S> {
-    fn search(&mut self, matches: &mut Vec<(u64, Match)>, pattern: &str) {
-        self.inner.search(matches, pattern)
+    fn search(
+        &self,
+        matcher: RegexMatcher,
+        from: u64,
+    ) -> Option<BackgroundSearch> {
+        self.inner.search(matcher, from)
     }
 }
 
@@ -263,75 +487,234 @@ impl<F: FileSwitcher> FileSwitcher for ValidReader<F> {
     fn next_file(&mut self) -> Result<()> {
         self.inner.next_file()
     }
+
+    fn switch_file(&mut self, index: usize) -> Result<()> {
+        self.inner.switch_file(index)
+    }
+
+    fn file_index(&self) -> usize {
+        self.inner.file_index()
+    }
+
+    fn file_count(&self) -> usize {
+        self.inner.file_count()
+    }
+
+    fn file_name(&self) -> &str {
+        self.inner.file_name()
+    }
+
+    fn file_names(&self) -> Vec<String> {
+        self.inner.file_names()
+    }
+
+    fn file_error(&self) -> Option<&Error> {
+        self.inner.file_error()
+    }
+
+    fn open_file(&mut self, path: &str) -> Result<()> {
+        self.inner.open_file(path)
+    }
+
+    fn close_file(&mut self) -> Result<()> {
+        self.inner.close_file()
+    }
+}
+
+impl<F: Filtering> Filtering for ValidReader<F> {
+    fn filter(&mut self, filter: Option<(RegexMatcher, bool)>) {
+        self.inner.filter(filter)
+    }
+
+    fn is_filtered(&self) -> bool {
+        self.inner.is_filtered()
+    }
+
+    fn original_offset(&self, offset: u64) -> u64 {
+        self.inner.original_offset(offset)
+    }
+
+    fn view_offset(&self, offset: u64) -> Option<u64> {
+        self.inner.view_offset(offset)
+    }
+
+    fn view_offset_after(&self, offset: u64) -> Option<u64> {
+        self.inner.view_offset_after(offset)
+    }
+
+    fn resume_filter(&mut self) {
+        self.inner.resume_filter()
+    }
+}
+
+impl<L: LineLookup> LineLookup for ValidReader<L> {
+    fn line_of_offset(&self, offset: u64) -> Option<u64> {
+        self.inner.line_of_offset(offset)
+    }
+
+    fn offset_of_line(&self, line: u64) -> Option<u64> {
+        self.inner.offset_of_line(line)
+    }
+
+    fn find_line(&self, line: u64) -> Result<LinePosition> {
+        self.inner.find_line(line)
+    }
+
+    fn line_count(&self) -> Option<u64> {
+        self.inner.line_count()
+    }
+}
+
+impl<S: Streaming> Streaming for ValidReader<S> {
+    fn is_eof(&self) -> bool {
+        self.inner.is_eof()
+    }
+
+    fn set_following(&mut self, following: bool) {
+        self.inner.set_following(following)
+    }
 }
 
 pub struct InputReader {
     input_type: InputType,
     current_file: usize,
+    /// One handle per input for reading on other threads, without moving
+    /// the position of the pager. `None` if the input couldn't be opened a
+    /// second time.
+    sources: Vec<Option<Arc<dyn ReadAt + Send + Sync>>>,
+    /// One line index per input, built in the background once the input
+    /// is looked at.
+    line_indexes: Vec<Option<LineIndex>>,
+    /// When set, reads and seeks are in the filtered view of the current
+    /// input.
+    filter: Option<Filter>,
+    /// Shared with the sources of the files, which may only grow while
+    /// the current one is followed.
+    following: Arc<AtomicBool>,
 }
 
 impl InputReader {
     pub fn new(input_type: InputType) -> InputReader {
+        let following = Arc::new(AtomicBool::new(false));
+        let sources: Vec<Option<Arc<dyn ReadAt + Send + Sync>>> =
+            match &input_type {
+                InputType::Stdin(stdin_cursor) => {
+                    vec![Some(stdin_cursor.buffer.clone())]
+                }
+                InputType::Files(files) => {
+                    files.iter().map(|f| source(f, &following)).collect()
+                }
+            };
+        let line_indexes = sources
+            .iter()
+            .map(|source| source.as_ref().map(|s| LineIndex::new(s.clone())))
+            .collect();
+        // Start with the first file that could be opened.
+        let current_file = match &input_type {
+            InputType::Files(files) => {
+                files.iter().position(|f| f.file.is_ok()).unwrap_or(0)
+            }
+            InputType::Stdin(_) => 0,
+        };
+
         InputReader {
             input_type: input_type,
-            current_file: 0,
+            current_file,
+            sources,
+            line_indexes,
+            filter: None,
+            following,
         }
     }
+
+    /// The line index of the current input, which is built from the
+    /// first time it's needed.
+    fn line_index(&self) -> Option<&LineIndex> {
+        let line_index = self.line_indexes.get(self.current_file)?.as_ref()?;
+        line_index.start();
+        Some(line_index)
+    }
+}
+
+/// A second handle to `input_file` for reading on other threads, `None` if
+/// it can't be opened again.
+fn source(
+    input_file: &InputFile,
+    following: &Arc<AtomicBool>,
+) -> Option<Arc<dyn ReadAt + Send + Sync>> {
+    let file = input_file.file.as_ref().ok()?.try_clone().ok()?;
+    Some(Arc::new(FileSource {
+        file,
+        following: following.clone(),
+    }))
+}
+
+/// A file read on another thread. It's only expected to grow while it's
+/// followed, otherwise the thread stops at its end.
+struct FileSource {
+    file: File,
+    following: Arc<AtomicBool>,
+}
+
+impl ReadAt for FileSource {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        self.file.read_at(buf, offset)
+    }
+
+    fn is_eof(&self) -> bool {
+        !self.following.load(Ordering::SeqCst)
+    }
 }
 
 impl Read for InputReader {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(filter) = &mut self.filter {
+            return filter.read(buf);
+        }
         return match &mut self.input_type {
             InputType::Stdin(stdin_cursor) => stdin_cursor.read(buf),
-            InputType::Files(files) => (&files[self.current_file]).read(buf),
+            InputType::Files(files) => {
+                match files[self.current_file].file.as_ref() {
+                    Ok(mut file) => file.read(buf),
+                    // A file that couldn't be opened is shown as empty.
+                    Err(_) => Ok(0),
+                }
+            }
         };
     }
 }
 
 impl Seek for InputReader {
     fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        if let Some(filter) = &mut self.filter {
+            return filter.seek(pos);
+        }
         return match &mut self.input_type {
             InputType::Stdin(stdin_cursor) => stdin_cursor.seek(pos),
-            InputType::Files(files) => (&files[self.current_file]).seek(pos),
+            InputType::Files(files) => {
+                match files[self.current_file].file.as_ref() {
+                    Ok(mut file) => file.seek(pos),
+                    Err(_) => Ok(0),
+                }
+            }
         };
     }
 }
 
 impl Search for InputReader {
-    fn search(&mut self, matches: &mut Vec<(u64, Match)>, pattern: &str) {
-        let matcher = match RegexMatcher::new(&pattern[..]) {
-            Err(_) => return,
-            Ok(m) => m,
-        };
-
-        let mut sink = StandardSink {
-            matcher: matcher,
-            matches: matches,
-            match_count: 0,
-        };
-
-        match &mut self.input_type {
-            InputType::Stdin(stdin_cursor) => {
-                match searcher::search_reader(&mut sink, stdin_cursor) {
-                    Err(_) => (),
-                    Ok(_) => (),
-                }
-            }
-            InputType::Files(files) => {
-                match searcher::search_file(
-                    &mut sink,
-                    &files[self.current_file],
-                ) {
-                    Err(_) => (),
-                    Ok(_) => (),
-                }
-            }
-        }
+    fn search(
+        &self,
+        matcher: RegexMatcher,
+        from: u64,
+    ) -> Option<BackgroundSearch> {
+        let source = self.sources.get(self.current_file)?.as_ref()?;
+        Some(BackgroundSearch::new(matcher, source.clone(), from))
     }
 }
 
 impl FileSwitcher for InputReader {
     fn next_file(&mut self) -> Result<()> {
+        self.filter = None;
         return match &mut self.input_type {
             InputType::Stdin(stdin_cursor) => stdin_cursor.next_file(),
             InputType::Files(files) => {
@@ -344,30 +727,287 @@ impl FileSwitcher for InputReader {
             }
         };
     }
+
+    fn switch_file(&mut self, index: usize) -> Result<()> {
+        if index >= self.file_count() {
+            return Err(Error::NoNextFile);
+        }
+        if let InputType::Stdin(stdin_cursor) = &mut self.input_type {
+            return stdin_cursor.switch_file(index);
+        }
+        self.filter = None;
+        self.current_file = index;
+        Ok(())
+    }
+
+    fn file_index(&self) -> usize {
+        self.current_file
+    }
+
+    fn file_count(&self) -> usize {
+        match &self.input_type {
+            InputType::Stdin(stdin_cursor) => stdin_cursor.file_count(),
+            InputType::Files(files) => files.len(),
+        }
+    }
+
+    fn file_name(&self) -> &str {
+        match &self.input_type {
+            InputType::Stdin(stdin_cursor) => stdin_cursor.file_name(),
+            InputType::Files(files) => &files[self.current_file].name,
+        }
+    }
+
+    fn file_names(&self) -> Vec<String> {
+        match &self.input_type {
+            InputType::Stdin(stdin_cursor) => stdin_cursor.file_names(),
+            InputType::Files(files) => {
+                files.iter().map(|f| f.name.clone()).collect()
+            }
+        }
+    }
+
+    fn file_error(&self) -> Option<&Error> {
+        match &self.input_type {
+            InputType::Stdin(_) => None,
+            InputType::Files(files) => {
+                files[self.current_file].file.as_ref().err()
+            }
+        }
+    }
+
+    fn open_file(&mut self, path: &str) -> Result<()> {
+        let files = match &mut self.input_type {
+            InputType::Stdin(stdin_cursor) => {
+                return stdin_cursor.open_file(path)
+            }
+            InputType::Files(files) => files,
+        };
+        let input_file = InputFile {
+            name: path.to_string(),
+            file: Ok(app::open(path)?),
+        };
+        let source = source(&input_file, &self.following);
+        self.line_indexes
+            .push(source.as_ref().map(|s| LineIndex::new(s.clone())));
+        self.sources.push(source);
+        files.push(input_file);
+
+        self.filter = None;
+        self.current_file = files.len() - 1;
+        Ok(())
+    }
+
+    fn close_file(&mut self) -> Result<()> {
+        let files = match &mut self.input_type {
+            InputType::Stdin(stdin_cursor) => return stdin_cursor.close_file(),
+            InputType::Files(files) => files,
+        };
+        if files.len() == 1 {
+            return Err(Error::Unsupported("Can't close the last file"));
+        }
+        files.remove(self.current_file);
+        self.sources.remove(self.current_file);
+        self.line_indexes.remove(self.current_file);
+
+        self.filter = None;
+        self.current_file = std::cmp::min(self.current_file, files.len() - 1);
+        Ok(())
+    }
+}
+
+impl Filtering for InputReader {
+    fn filter(&mut self, filter: Option<(RegexMatcher, bool)>) {
+        self.filter = match filter {
+            Some((matcher, invert)) => self
+                .sources
+                .get(self.current_file)
+                .and_then(|source| source.clone())
+                .map(|source| Filter::new(matcher, source, invert)),
+            None => None,
+        };
+    }
+
+    fn is_filtered(&self) -> bool {
+        self.filter.is_some()
+    }
+
+    fn original_offset(&self, offset: u64) -> u64 {
+        match &self.filter {
+            Some(filter) => filter.original_offset(offset),
+            None => offset,
+        }
+    }
+
+    fn view_offset(&self, offset: u64) -> Option<u64> {
+        match &self.filter {
+            Some(filter) => filter.view_offset(offset),
+            None => Some(offset),
+        }
+    }
+
+    fn view_offset_after(&self, offset: u64) -> Option<u64> {
+        match &self.filter {
+            Some(filter) => filter.view_offset_after(offset),
+            None => Some(offset),
+        }
+    }
+
+    fn resume_filter(&mut self) {
+        if let Some(filter) = &mut self.filter {
+            filter.resume();
+        }
+    }
+}
+
+/// Lines are those of the input, also when it's filtered.
+impl LineLookup for InputReader {
+    fn line_of_offset(&self, offset: u64) -> Option<u64> {
+        let offset = self.original_offset(offset);
+        self.line_index()?.line_of_offset(offset)
+    }
+
+    fn offset_of_line(&self, line: u64) -> Option<u64> {
+        let offset = self.line_index()?.offset_of_line(line)?;
+        self.view_offset(offset)
+    }
+
+    fn find_line(&self, line: u64) -> Result<LinePosition> {
+        let line_index = match self.line_index() {
+            Some(line_index) => line_index,
+            None => return Ok(LinePosition::PastEnd),
+        };
+        Ok(match line_index.find_line(line)? {
+            // The filter may not have got to the line yet.
+            LinePosition::At(offset) => match self.view_offset_after(offset) {
+                Some(offset) => LinePosition::At(offset),
+                None => LinePosition::Unknown,
+            },
+            position => position,
+        })
+    }
+
+    fn line_count(&self) -> Option<u64> {
+        self.line_index()?.line_count()
+    }
+}
+
+impl Streaming for InputReader {
+    fn is_eof(&self) -> bool {
+        if let Some(filter) = &self.filter {
+            if !filter.is_done() {
+                return false;
+            }
+        }
+        match &self.input_type {
+            InputType::Stdin(stdin_cursor) => stdin_cursor.is_eof(),
+            InputType::Files(_) => true,
+        }
+    }
+
+    fn set_following(&mut self, following: bool) {
+        self.following.store(following, Ordering::SeqCst);
+    }
 }
 
 impl StdinCursor {
+    /// Starts reading `stdin_file` in the background. The data read so far
+    /// is available immediately, the rest is appended as it arrives.
     pub fn new(mut stdin_file: File) -> StdinCursor {
-        let mut cursor: Cursor<Vec<u8>> = Cursor::new(Vec::new());
-        // TODO: Currently reads the whole thing at the beginning. Could instead read while
-        // scrolling down.
-        stdin_file.read_to_end(cursor.get_mut()).unwrap();
-        StdinCursor {
-            cursor: cursor,
-            file: stdin_file,
-        }
+        let buffer = Arc::new(StdinBuffer::new());
+
+        let thread_buffer = buffer.clone();
+        thread::spawn(move || {
+            let mut chunk = vec![0; STDIN_CHUNK_SIZE];
+            loop {
+                match stdin_file.read(&mut chunk) {
+                    Ok(0) => break,
+                    Ok(n) => thread_buffer.append(&chunk[..n]),
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {
+                        continue
+                    }
+                    Err(_) => break,
+                }
+            }
+            thread_buffer.close();
+        });
+
+        StdinCursor { buffer, pos: 0 }
+    }
+
+    fn len(&self) -> u64 {
+        self.buffer.data.lock().unwrap().len() as u64
     }
 }
 
+impl StdinBuffer {
+    pub fn new() -> StdinBuffer {
+        StdinBuffer::default()
+    }
+
+    pub fn append(&self, data: &[u8]) {
+        self.data.lock().unwrap().extend_from_slice(data);
+    }
+
+    /// Marks the end of stdin, nothing more is appended after this.
+    pub fn close(&self) {
+        self.eof.store(true, Ordering::SeqCst);
+    }
+}
+
+impl ReadAt for StdinBuffer {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let data = self.data.lock().unwrap();
+        let start = std::cmp::min(offset, data.len() as u64) as usize;
+        let n = std::cmp::min(buf.len(), data.len() - start);
+
+        buf[..n].copy_from_slice(&data[start..start + n]);
+        Ok(n)
+    }
+
+    fn is_eof(&self) -> bool {
+        self.eof.load(Ordering::SeqCst)
+    }
+}
+
+impl Streaming for StdinCursor {
+    fn is_eof(&self) -> bool {
+        self.buffer.eof.load(Ordering::SeqCst)
+    }
+
+    /// A pipe ends when it's closed, following it doesn't change that.
+    fn set_following(&mut self, _following: bool) {}
+}
+
 impl Seek for StdinCursor {
     fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
-        self.cursor.seek(pos)
+        let (base, offset) = match pos {
+            SeekFrom::Start(n) => {
+                self.pos = n;
+                return Ok(n);
+            }
+            SeekFrom::End(n) => (self.len(), n),
+            SeekFrom::Current(n) => (self.pos, n),
+        };
+
+        let new_pos = base as i64 + offset;
+        if new_pos < 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative position",
+            ));
+        }
+
+        self.pos = new_pos as u64;
+        Ok(self.pos)
     }
 }
 
 impl Read for StdinCursor {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.cursor.read(buf)
+        let n = self.buffer.read_at(buf, self.pos)?;
+        self.pos += n as u64;
+        Ok(n)
     }
 }
 
@@ -375,6 +1015,42 @@ impl FileSwitcher for StdinCursor {
     fn next_file(&mut self) -> Result<()> {
         Err(Error::NoNextFile)
     }
+
+    fn switch_file(&mut self, index: usize) -> Result<()> {
+        match index {
+            0 => Ok(()),
+            _ => Err(Error::NoNextFile),
+        }
+    }
+
+    fn file_index(&self) -> usize {
+        0
+    }
+
+    fn file_count(&self) -> usize {
+        1
+    }
+
+    fn file_name(&self) -> &str {
+        ""
+    }
+
+    fn file_names(&self) -> Vec<String> {
+        vec![String::new()]
+    }
+
+    fn file_error(&self) -> Option<&Error> {
+        None
+    }
+
+    /// Stdin can't be viewed along with files.
+    fn open_file(&mut self, _path: &str) -> Result<()> {
+        Err(Error::Unsupported("Can't open files along with stdin"))
+    }
+
+    fn close_file(&mut self) -> Result<()> {
+        Err(Error::Unsupported("Can't close stdin"))
+    }
 }
 
 #[cfg(test)]
@@ -424,6 +1100,49 @@ mod tests {
         assert!(std::str::from_utf8(&buf[..b]).is_err());
     }
 
+    #[test]
+    fn test_size_keeps_position() {
+        let mut reader = BiBufReader::new(ValidReader::new(thai_file()), true);
+        reader.jump_offset(3).unwrap();
+
+        let size = thai_file().metadata().unwrap().len();
+        assert_eq!(reader.size().unwrap(), size);
+        assert_eq!(reader.current_offset(), 3);
+    }
+
+    #[test]
+    fn test_peek_keeps_position() {
+        let input = io::Cursor::new(b"abc\ndef".to_vec());
+        let mut reader = BiBufReader::new(ValidReader::new(input), false);
+        reader.jump_offset(2).unwrap();
+
+        assert_eq!(reader.peek(3).unwrap(), b"c\nd");
+        assert_eq!(reader.peek(100).unwrap(), b"c\ndef");
+        assert_eq!(reader.current_offset(), 2);
+    }
+
+    #[test]
+    fn test_stdin_cursor_reads_everything() {
+        let mut cursor = StdinCursor::new(thai_file());
+        while !cursor.is_eof() {
+            thread::yield_now();
+        }
+
+        let mut expected = Vec::new();
+        thai_file().read_to_end(&mut expected).unwrap();
+
+        let mut buf = Vec::new();
+        cursor.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, expected);
+
+        assert_eq!(
+            cursor.seek(SeekFrom::End(-3)).unwrap(),
+            buf.len() as u64 - 3
+        );
+        assert_eq!(cursor.read(&mut [0; 8]).unwrap(), 3);
+        assert!(cursor.seek(SeekFrom::Current(-100_000)).is_err());
+    }
+
     #[test]
     fn test_bufreader_fails_start() {
         let mut reader = BufReader::new(thai_file());
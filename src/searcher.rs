@@ -1,29 +1,434 @@
-use std::fs::File;
-use std::io::Read;
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread;
 
-use grep::searcher::Searcher;
+use grep::matcher::Match;
+use grep::regex::{self, RegexMatcher, RegexMatcherBuilder};
+use grep::searcher::SearcherBuilder;
 
 use error::{Error, Result};
+use line_index::{ReadAt, IDLE_INTERVAL};
 use standard::StandardSink;
 
-// For stdin
+/// Searches `reader` to the end, or for the lines that don't match if
+/// `invert` is set.
 pub fn search_reader<R: Read>(
     sink: &mut StandardSink,
     reader: R,
+    invert: bool,
 ) -> Result<()> {
-    return match Searcher::new().search_reader(
-        sink.matcher.clone(),
-        reader,
-        sink,
-    ) {
-        Err(_) => Err(Error::SearchError),
+    let mut searcher = SearcherBuilder::new().invert_match(invert).build();
+    match searcher.search_reader(sink.matcher.clone(), reader, sink) {
+        Err(e) => Err(Error::from(e)),
         Ok(_) => Ok(()),
-    };
+    }
 }
 
-pub fn search_file(sink: &mut StandardSink, file: &File) -> Result<()> {
-    return match Searcher::new().search_file(sink.matcher.clone(), file, sink) {
-        Err(_) => Err(Error::SearchError),
-        Ok(_) => Ok(()),
-    };
+/// How letter case is treated when searching.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CaseMode {
+    Sensitive,
+    /// Ignore case unless the pattern has upper case letters.
+    Smart,
+    Insensitive,
+}
+
+/// Modifiers for how a search pattern is matched.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SearchOptions {
+    pub case: CaseMode,
+    /// Match the pattern as plain text instead of a regex.
+    pub literal: bool,
+    /// Only match whole words.
+    pub word: bool,
+}
+
+impl Default for SearchOptions {
+    fn default() -> SearchOptions {
+        SearchOptions {
+            case: CaseMode::Sensitive,
+            literal: false,
+            word: false,
+        }
+    }
+}
+
+impl SearchOptions {
+    /// Builds a matcher for `pattern`. A leading `(?i)` turns on ignoring
+    /// case, also for literal patterns.
+    pub fn matcher(
+        &self,
+        pattern: &str,
+    ) -> std::result::Result<RegexMatcher, regex::Error> {
+        let (case, pattern) = match pattern.strip_prefix("(?i)") {
+            Some(pattern) => (CaseMode::Insensitive, pattern),
+            None => (self.case, pattern),
+        };
+        let pattern = if self.literal {
+            escape(pattern)
+        } else {
+            pattern.to_string()
+        };
+
+        // The searcher hands over many lines at once, so `^` and `$` have to
+        // match at every line, and matches must not span lines.
+        RegexMatcherBuilder::new()
+            .multi_line(true)
+            .line_terminator(Some(b'\n'))
+            .case_insensitive(case == CaseMode::Insensitive)
+            .case_smart(case == CaseMode::Smart)
+            .word(self.word)
+            .build(&pattern)
+    }
+
+    /// Moves on to the next case mode.
+    pub fn cycle_case(&mut self) {
+        self.case = match self.case {
+            CaseMode::Sensitive => CaseMode::Smart,
+            CaseMode::Smart => CaseMode::Insensitive,
+            CaseMode::Insensitive => CaseMode::Sensitive,
+        };
+    }
+
+    /// The active modifiers, to show in front of the search prompt.
+    pub fn describe(&self) -> String {
+        let mut modifiers = Vec::new();
+        match self.case {
+            CaseMode::Sensitive => (),
+            CaseMode::Smart => modifiers.push("Smart-case "),
+            CaseMode::Insensitive => modifiers.push("Ignore-case "),
+        }
+        if self.literal {
+            modifiers.push("Regex-off ");
+        }
+        if self.word {
+            modifiers.push("Word ");
+        }
+        modifiers.concat()
+    }
+}
+
+/// Escapes the characters that have a meaning in a regex.
+fn escape(pattern: &str) -> String {
+    let mut escaped = String::with_capacity(pattern.len());
+    for c in pattern.chars() {
+        if "\\.+*?()|[]{}^$#&-~".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// A search running on its own thread. Matches are sent back in order as
+/// they are found, so the first ones can be shown before the whole input
+/// has been searched.
+///
+/// At the end of an input that may still grow the search waits for more,
+/// until it's cancelled.
+pub struct BackgroundSearch {
+    receiver: Receiver<(u64, Match)>,
+    /// Bytes searched so far.
+    searched: Arc<AtomicU64>,
+    /// The end of the last complete line searched so far.
+    lines_end: Arc<AtomicU64>,
+    cancelled: Arc<AtomicBool>,
+    done: bool,
+}
+
+impl BackgroundSearch {
+    /// Searches from `from` on, which has to be the start of a line.
+    pub fn new(
+        matcher: RegexMatcher,
+        source: Arc<dyn ReadAt + Send + Sync>,
+        from: u64,
+    ) -> BackgroundSearch {
+        BackgroundSearch::start(matcher, source, from, false, false)
+    }
+
+    /// Searches for whole lines instead of matches within them, each sent
+    /// as a match spanning the line. With `invert` the lines that don't
+    /// match are sent.
+    pub fn lines(
+        matcher: RegexMatcher,
+        source: Arc<dyn ReadAt + Send + Sync>,
+        from: u64,
+        invert: bool,
+    ) -> BackgroundSearch {
+        BackgroundSearch::start(matcher, source, from, invert, true)
+    }
+
+    fn start(
+        matcher: RegexMatcher,
+        source: Arc<dyn ReadAt + Send + Sync>,
+        from: u64,
+        invert: bool,
+        whole_lines: bool,
+    ) -> BackgroundSearch {
+        let (sender, receiver) = channel();
+        let searched = Arc::new(AtomicU64::new(from));
+        let lines_end = Arc::new(AtomicU64::new(from));
+        let cancelled = Arc::new(AtomicBool::new(false));
+
+        let reader = SourceReader {
+            source,
+            pos: from,
+            searched: searched.clone(),
+            lines_end: lines_end.clone(),
+            cancelled: cancelled.clone(),
+        };
+        thread::spawn(move || {
+            let mut sink = StandardSink {
+                matcher,
+                sender,
+                match_count: 0,
+                start: from,
+                whole_lines,
+            };
+            let _ = search_reader(&mut sink, reader, invert);
+        });
+
+        BackgroundSearch {
+            receiver,
+            searched,
+            lines_end,
+            cancelled,
+            done: false,
+        }
+    }
+
+    /// Appends the matches found since the last call to `matches`. Returns
+    /// true if there were any, or if the search has just finished.
+    pub fn receive(&mut self, matches: &mut Vec<(u64, Match)>) -> bool {
+        let mut changed = false;
+        loop {
+            match self.receiver.try_recv() {
+                Ok(m) => {
+                    matches.push(m);
+                    changed = true;
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    changed = changed || !self.done;
+                    self.done = true;
+                    break;
+                }
+            }
+        }
+        changed
+    }
+
+    /// True once every match has been received.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    pub fn searched(&self) -> u64 {
+        self.searched.load(Ordering::SeqCst)
+    }
+
+    /// Where to go on from once the search is done, to search what has
+    /// been appended to the input since. That's after the last complete
+    /// line, as the last line may have been cut short by the end of the
+    /// input. Matches from there on are found again.
+    pub fn resume_point(&self) -> u64 {
+        self.lines_end.load(Ordering::SeqCst)
+    }
+
+    /// Stops the search thread. Matches already found can still be
+    /// received.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+}
+
+impl Drop for BackgroundSearch {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+/// Reads a `ReadAt`, keeping track of how far it has got. At the end of an
+/// input that may still grow, waits for more instead of ending. Ends early
+/// when the search is cancelled.
+struct SourceReader {
+    source: Arc<dyn ReadAt + Send + Sync>,
+    pos: u64,
+    searched: Arc<AtomicU64>,
+    lines_end: Arc<AtomicU64>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Read for SourceReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.cancelled.load(Ordering::SeqCst) {
+                return Ok(0);
+            }
+            // Check before reading, so data appended after the read isn't
+            // mistaken for the end.
+            let eof = self.source.is_eof();
+            let n = self.source.read_at(buf, self.pos)?;
+            if n == 0 && !eof {
+                thread::sleep(IDLE_INTERVAL);
+                continue;
+            }
+
+            if let Some(i) = buf[..n].iter().rposition(|&b| b == b'\n') {
+                let lines_end = self.pos + i as u64 + 1;
+                self.lines_end.store(lines_end, Ordering::SeqCst);
+            }
+            self.pos += n as u64;
+            self.searched.store(self.pos, Ordering::SeqCst);
+            return Ok(n);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use reader::StdinBuffer;
+
+    fn search_all(path: &str, pattern: &str) -> Vec<(u64, Match)> {
+        let source = Arc::new(::std::fs::File::open(path).unwrap());
+        let matcher = RegexMatcher::new(pattern).unwrap();
+        let mut search = BackgroundSearch::new(matcher, source, 0);
+        let mut matches = Vec::new();
+        receive_all(&mut search, &mut matches);
+        matches
+    }
+
+    fn receive_all(
+        search: &mut BackgroundSearch,
+        matches: &mut Vec<(u64, Match)>,
+    ) {
+        while !search.is_done() {
+            search.receive(matches);
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Receives until there are `n` matches, which may take a while for
+    /// a growing input.
+    fn receive_until(
+        search: &mut BackgroundSearch,
+        matches: &mut Vec<(u64, Match)>,
+        n: usize,
+    ) {
+        while matches.len() < n {
+            search.receive(matches);
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_background_search() {
+        let matches = search_all("tests/resources/bible_short.txt", "the");
+        assert!(!matches.is_empty());
+        assert!(matches.windows(2).all(|w| w[0].0 <= w[1].0));
+
+        let text = ::std::fs::read("tests/resources/bible_short.txt").unwrap();
+        for (offset, m) in matches {
+            let start = offset as usize + m.start();
+            assert_eq!(&text[start..start + 3], b"the");
+        }
+    }
+
+    #[test]
+    fn test_growing_input() {
+        let buffer = Arc::new(StdinBuffer::new());
+        buffer.append(b"a foo\nb");
+        let matcher = RegexMatcher::new("foo").unwrap();
+        let mut search = BackgroundSearch::new(matcher, buffer.clone(), 0);
+
+        let mut matches = Vec::new();
+        receive_until(&mut search, &mut matches, 1);
+        buffer.append(b" foo\nc foo\n");
+        receive_until(&mut search, &mut matches, 3);
+        assert!(!search.is_done());
+
+        buffer.close();
+        receive_all(&mut search, &mut matches);
+        let offsets: Vec<u64> = matches.iter().map(|m| m.0).collect();
+        assert_eq!(offsets, vec![0, 6, 12]);
+        assert_eq!(search.searched(), 18);
+    }
+
+    #[test]
+    fn test_resume_point() {
+        let text = b"a foo\nb fo";
+        let buffer = Arc::new(StdinBuffer::new());
+        buffer.append(text);
+        buffer.close();
+        let matcher = RegexMatcher::new("foo").unwrap();
+        let mut search = BackgroundSearch::new(matcher.clone(), buffer, 0);
+        let mut matches = Vec::new();
+        receive_all(&mut search, &mut matches);
+        assert_eq!(matches.len(), 1);
+        // The last line is searched again, it may have been incomplete.
+        assert_eq!(search.resume_point(), 6);
+
+        let buffer = Arc::new(StdinBuffer::new());
+        buffer.append(b"a foo\nb foo\n");
+        buffer.close();
+        let mut search = BackgroundSearch::new(matcher, buffer, 6);
+        let mut matches = Vec::new();
+        receive_all(&mut search, &mut matches);
+        let found: Vec<(u64, usize)> = matches
+            .iter()
+            .map(|(offset, m)| (*offset, m.start()))
+            .collect();
+        assert_eq!(found, vec![(6, 2)]);
+    }
+
+    #[test]
+    fn test_search_options() {
+        use grep::matcher::Matcher;
+
+        let is_match = |options: SearchOptions, pattern: &str, text: &str| {
+            let matcher = options.matcher(pattern).unwrap();
+            matcher.is_match(text.as_bytes()).unwrap()
+        };
+        let mut options = SearchOptions::default();
+        assert!(!is_match(options, "abc", "ABC"));
+        assert!(is_match(options, "(?i)abc", "ABC"));
+
+        options.case = CaseMode::Smart;
+        assert!(is_match(options, "abc", "ABC"));
+        assert!(!is_match(options, "Abc", "ABC"));
+
+        options.literal = true;
+        assert!(is_match(options, "a.c(", "xa.c("));
+        assert!(!is_match(options, "a.c", "abc"));
+        assert!(is_match(options, "(?i)A.C", "a.c"));
+
+        options = SearchOptions::default();
+        assert!(is_match(options, "^b", "a\nb"));
+
+        options.word = true;
+        assert!(is_match(options, "ab", "x ab y"));
+        assert!(!is_match(options, "ab", "xaby"));
+    }
+
+    #[test]
+    fn test_describe() {
+        let mut options = SearchOptions::default();
+        assert_eq!(options.describe(), "");
+        options.cycle_case();
+        options.literal = true;
+        assert_eq!(options.describe(), "Smart-case Regex-off ");
+    }
+
+    #[test]
+    fn test_no_match() {
+        assert!(
+            search_all("tests/resources/bible_short.txt", "zzzzqqq").is_empty()
+        );
+    }
 }
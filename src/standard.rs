@@ -1,22 +1,35 @@
 use std::io;
+use std::sync::mpsc::Sender;
 
 use grep::matcher::{Match, Matcher};
 use grep::regex::RegexMatcher;
 use grep::searcher::{Searcher, Sink, SinkMatch};
 
+/// Sends every match on, together with the offset of the line it's in.
 #[derive(Debug)]
-pub struct StandardSink<'a> {
+pub struct StandardSink {
     pub matcher: RegexMatcher,
-    pub matches: &'a mut Vec<(u64, Match)>,
+    pub sender: Sender<(u64, Match)>,
     pub match_count: u64,
+    /// The offset in the input the searched reader starts at.
+    pub start: u64,
+    /// Send one match spanning each matching line, terminator included,
+    /// instead of the matches within it.
+    pub whole_lines: bool,
 }
 
-impl<'a> StandardSink<'a> {
-    fn record_match(&mut self, mat: &SinkMatch) -> io::Result<()> {
+impl StandardSink {
+    /// Returns false if nobody is listening for matches anymore.
+    fn record_match(&mut self, mat: &SinkMatch) -> io::Result<bool> {
         let bytes = mat.bytes();
-        let offset = mat.absolute_byte_offset();
+        let offset = self.start + mat.absolute_byte_offset();
 
-        let matches = &mut self.matches;
+        if self.whole_lines {
+            let line = Match::new(0, bytes.len());
+            return Ok(self.sender.send((offset, line)).is_ok());
+        }
+
+        let mut matches = Vec::new();
         self.matcher.find_iter(bytes, |m| {
             matches.push((offset, m));
             true
@@ -29,11 +42,17 @@ impl<'a> StandardSink<'a> {
         {
             matches.pop().unwrap();
         }
-        Ok(())
+
+        for m in matches {
+            if self.sender.send(m).is_err() {
+                return Ok(false);
+            }
+        }
+        Ok(true)
     }
 }
 
-impl<'a> Sink for StandardSink<'a> {
+impl Sink for StandardSink {
     type Error = io::Error;
 
     fn matched(
@@ -42,8 +61,6 @@ impl<'a> Sink for StandardSink<'a> {
         mat: &SinkMatch,
     ) -> Result<bool, io::Error> {
         self.match_count += 1;
-        self.record_match(mat)?;
-
-        Ok(true)
+        self.record_match(mat)
     }
 }
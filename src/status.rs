@@ -0,0 +1,292 @@
+/// The prompt shown when no `--prompt` is given.
+pub const DEFAULT_PROMPT: &str =
+    "?f%f:(standard input). ?m(file %i of %m) .?ltlines %lt-%lb?L/%L. .\
+     byte %bb?B/%B. ?e(END):?pb%pb\\%..%o";
+
+/// What the status line knows about the current view.
+#[derive(Debug, Default)]
+pub struct Status {
+    /// Empty for stdin.
+    pub file_name: String,
+    /// One indexed.
+    pub file_index: usize,
+    pub file_count: usize,
+    pub top_line: Option<u64>,
+    pub bottom_line: Option<u64>,
+    pub line_count: Option<u64>,
+    pub top_offset: u64,
+    pub bottom_offset: u64,
+    pub size: u64,
+    /// The input may still grow, e.g. a pipe that is still open.
+    pub streaming: bool,
+    pub following: bool,
+    pub wrap: bool,
+    /// Bytes searched so far, while a search is running.
+    pub searched: Option<u64>,
+    /// Only some lines are shown.
+    pub filtered: bool,
+    /// The match last jumped to, one indexed.
+    pub current_match: Option<usize>,
+    /// Matches found so far, when there is a search.
+    pub match_count: Option<usize>,
+    /// The last jump to a match went on from the other end.
+    pub search_wrapped: bool,
+    /// A line to jump to once the index gets to it.
+    pub indexing: Option<u64>,
+}
+
+impl Status {
+    /// Formats the status according to `prompt`, which uses a subset of the
+    /// syntax of less's `-P` option.
+    ///
+    /// `%X` is replaced by the value of `X`, and `?X...:....` shows the
+    /// first part if `X` is known (or true), the optional second part
+    /// otherwise. Conditionals can be nested. `\` escapes the next
+    /// character. The values are:
+    ///
+    /// * `f` file name, `i` file index, `m` number of files
+    /// * `lt`/`lb` line at the top/bottom of the screen, `L` number of lines
+    /// * `bt`/`bb` byte offset of the top/bottom of the screen, `B` size
+    /// * `pt`/`pb` percentage into the input of the top/bottom of the screen
+    /// * `e` true at the end of the input
+    /// * `o` mode flags, e.g. `[follow]`
+    pub fn format(&self, prompt: &str) -> String {
+        let mut out = String::new();
+        // For every open conditional: whether its condition held, and
+        // whether its else branch has been reached.
+        let mut conditionals: Vec<(bool, bool)> = Vec::new();
+        let mut chars = prompt.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            let showing = conditionals
+                .iter()
+                .all(|&(condition, in_else)| condition != in_else);
+
+            match c {
+                '\\' => {
+                    if let Some(c) = chars.next() {
+                        if showing {
+                            out.push(c);
+                        }
+                    }
+                }
+                '%' => {
+                    let key = read_key(&mut chars);
+                    if showing {
+                        out.push_str(
+                            &self.value(&key).unwrap_or(String::from("?")),
+                        );
+                    }
+                }
+                '?' => {
+                    let key = read_key(&mut chars);
+                    conditionals.push((self.condition(&key), false));
+                }
+                ':' if !conditionals.is_empty() => {
+                    conditionals.last_mut().unwrap().1 = true;
+                }
+                '.' if !conditionals.is_empty() => {
+                    conditionals.pop();
+                }
+                c => {
+                    if showing {
+                        out.push(c);
+                    }
+                }
+            }
+        }
+
+        out
+    }
+
+    fn value(&self, key: &str) -> Option<String> {
+        match key {
+            "f" => Some(self.file_name.clone()),
+            "i" => Some(self.file_index.to_string()),
+            "m" => Some(self.file_count.to_string()),
+            "lt" => self.top_line.map(|l| l.to_string()),
+            "lb" => self.bottom_line.map(|l| l.to_string()),
+            "L" => self.line_count.map(|l| l.to_string()),
+            "bt" => Some(self.top_offset.to_string()),
+            "bb" => Some(self.bottom_offset.to_string()),
+            "B" => Some(self.size.to_string()),
+            "pt" => self.percent(self.top_offset).map(|p| p.to_string()),
+            "pb" => self.percent(self.bottom_offset).map(|p| p.to_string()),
+            "o" => Some(self.flags()),
+            _ => None,
+        }
+    }
+
+    fn condition(&self, key: &str) -> bool {
+        match key {
+            "f" => !self.file_name.is_empty(),
+            "m" => self.file_count > 1,
+            "B" => self.size > 0,
+            "e" => self.is_at_end(),
+            "o" => !self.flags().is_empty(),
+            key => self.value(key).is_some(),
+        }
+    }
+
+    fn is_at_end(&self) -> bool {
+        !self.streaming && self.bottom_offset >= self.size
+    }
+
+    fn percent(&self, offset: u64) -> Option<u64> {
+        offset
+            .min(self.size)
+            .checked_mul(100)?
+            .checked_div(self.size)
+    }
+
+    fn flags(&self) -> String {
+        let mut flags = Vec::new();
+        if self.following {
+            flags.push(" [follow]");
+        } else if self.streaming {
+            flags.push(" [waiting for data]");
+        }
+        if !self.wrap {
+            flags.push(" [chop]");
+        }
+        if self.filtered {
+            flags.push(" [filtered]");
+        }
+        let mut flags = flags.concat();
+        match (self.current_match, self.match_count) {
+            (Some(current), Some(count)) => {
+                flags.push_str(&format!(" match {} of {}", current, count))
+            }
+            (None, Some(1)) => flags.push_str(" 1 match"),
+            (None, Some(count)) => {
+                flags.push_str(&format!(" {} matches", count))
+            }
+            _ => (),
+        }
+        if self.search_wrapped {
+            flags.push_str(" [search wrapped]");
+        }
+        if let Some(searched) = self.searched {
+            let percent = self.percent(searched).unwrap_or(0);
+            flags.push_str(&format!(" [searching {}%]", percent));
+        }
+        if let Some(line) = self.indexing {
+            flags.push_str(&format!(" [indexing to line {}]", line));
+        }
+        flags
+    }
+}
+
+/// Reads the name of a value after `%` or `?`. Line, byte and percent
+/// values take a second letter saying if they're for the top or bottom of
+/// the screen.
+fn read_key<I: Iterator<Item = char>>(
+    chars: &mut std::iter::Peekable<I>,
+) -> String {
+    let mut key = String::new();
+    if let Some(c) = chars.next() {
+        key.push(c);
+        if c == 'l' || c == 'b' || c == 'p' {
+            if let Some(&position) = chars.peek() {
+                if position == 't' || position == 'b' {
+                    key.push(position);
+                    chars.next();
+                }
+            }
+        }
+    }
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status() -> Status {
+        Status {
+            file_name: String::from("log.txt"),
+            file_index: 2,
+            file_count: 5,
+            top_line: Some(11),
+            bottom_line: Some(30),
+            line_count: None,
+            top_offset: 100,
+            bottom_offset: 250,
+            size: 1000,
+            streaming: false,
+            following: false,
+            wrap: true,
+            searched: None,
+            filtered: false,
+            current_match: None,
+            match_count: None,
+            search_wrapped: false,
+            indexing: None,
+        }
+    }
+
+    #[test]
+    fn test_values() {
+        let status = status();
+        assert_eq!(status.format("%f %i/%m"), "log.txt 2/5");
+        assert_eq!(status.format("%lt-%lb of %L"), "11-30 of ?");
+        assert_eq!(status.format("%bt %bb %B %pt %pb"), "100 250 1000 10 25");
+        assert_eq!(status.format("100\\% %%"), "100% ?");
+    }
+
+    #[test]
+    fn test_conditionals() {
+        let mut status = status();
+        assert_eq!(status.format("?L%L:no count."), "no count");
+        assert_eq!(status.format("?m(%i of %m).!"), "(2 of 5)!");
+        assert_eq!(status.format("?e(END):?pb%pb\\%:?..."), "25%");
+
+        status.file_count = 1;
+        status.bottom_offset = 1000;
+        assert_eq!(status.format("a?mb:c?ed.e.f"), "acdef");
+        assert_eq!(status.format("?m(%i of %m).!"), "!");
+    }
+
+    #[test]
+    fn test_default_prompt() {
+        let mut status = status();
+        assert_eq!(
+            status.format(DEFAULT_PROMPT),
+            "log.txt (file 2 of 5) lines 11-30 byte 250/1000 25%"
+        );
+
+        status.file_name = String::new();
+        status.file_count = 1;
+        status.line_count = Some(30);
+        status.bottom_offset = 1000;
+        status.wrap = false;
+        status.following = true;
+        assert_eq!(
+            status.format(DEFAULT_PROMPT),
+            "(standard input) lines 11-30/30 byte 1000/1000 (END) [follow] \
+             [chop]"
+        );
+
+        status.following = false;
+        status.wrap = true;
+        status.searched = Some(420);
+        assert_eq!(status.format("%o"), " [searching 42%]");
+        status.searched = None;
+        status.indexing = Some(5000);
+        assert_eq!(status.format("%o"), " [indexing to line 5000]");
+    }
+
+    #[test]
+    fn test_match_counter() {
+        let mut status = status();
+        status.match_count = Some(132);
+        assert_eq!(status.format("%o"), " 132 matches");
+        status.match_count = Some(1);
+        assert_eq!(status.format("%o"), " 1 match");
+        status.match_count = Some(132);
+
+        status.current_match = Some(7);
+        status.search_wrapped = true;
+        assert_eq!(status.format("%o"), " match 7 of 132 [search wrapped]");
+    }
+}
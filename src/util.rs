@@ -2,6 +2,13 @@ use std::i32;
 
 use termion::terminal_size;
 use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use ansi;
+
+/// Columns taken by the line number gutter, including the space after the
+/// number.
+pub const LINE_NUMBER_WIDTH: u16 = 8;
 
 pub fn screen_height_half() -> usize {
     (screen_height() as usize - 1) / 2
@@ -11,6 +18,16 @@ pub fn screen_width_height() -> (u16, u16) {
     terminal_size().unwrap()
 }
 
+/// The columns left for text once the line number gutter, if shown, has
+/// taken its share.
+pub fn text_width(screen_width: u16, line_numbers: bool) -> u16 {
+    if line_numbers {
+        std::cmp::max(1, screen_width.saturating_sub(LINE_NUMBER_WIDTH))
+    } else {
+        screen_width
+    }
+}
+
 pub fn screen_height() -> usize {
     let (_, screen_height) = terminal_size().unwrap();
     screen_height as usize - 1
@@ -20,16 +37,60 @@ pub fn grapheme_count(buf: &str) -> usize {
     UnicodeSegmentation::graphemes(buf, true).count()
 }
 
+/// The columns `buf` takes up on screen, where e.g. CJK characters take
+/// two.
+pub fn display_width(buf: &str) -> usize {
+    UnicodeWidthStr::width(buf)
+}
+
+/// The part of `text` to show in `width` columns so that `cursor`, a byte
+/// offset, is visible, leaving a column free for the cursor at the end.
+/// Returns the byte range to show and the column of the cursor in it.
+pub fn cursor_window(
+    text: &str,
+    cursor: usize,
+    width: usize,
+) -> ((usize, usize), usize) {
+    let room = width.saturating_sub(1);
+
+    // Drop graphemes from the start until the cursor fits.
+    let mut start = 0;
+    let mut before = display_width(&text[..cursor]);
+    for (i, g) in text[..cursor].grapheme_indices(true) {
+        if before <= room {
+            break;
+        }
+        start = i + g.len();
+        before -= display_width(g);
+    }
+
+    let mut end = start;
+    let mut used = 0;
+    for g in text[start..].graphemes(true) {
+        used += display_width(g);
+        if used > room {
+            break;
+        }
+        end += g.len();
+    }
+    ((start, end), before)
+}
+
+/// With `ansi` set, escape sequences in `buf` take up no width.
 pub fn nth_newline_pos(
     mut n: usize,
     buf: &str,
     screen_width: Option<i32>,
+    ansi: bool,
 ) -> usize {
     let mut grapheme_count = 0;
     let mut current_pos = 0;
-    for (offset, grapheme) in UnicodeSegmentation::grapheme_indices(buf, true) {
-        grapheme_count += 1;
+    for (offset, grapheme) in ansi::graphemes(buf, ansi) {
         current_pos = offset + grapheme_size(grapheme);
+        if ansi && ansi::is_escape(grapheme) {
+            continue;
+        }
+        grapheme_count += 1;
         if is_newline(grapheme)
             || grapheme_count >= screen_width.unwrap_or(i32::MAX)
         {
@@ -44,15 +105,20 @@ pub fn nth_newline_pos(
     current_pos
 }
 
+/// With `ansi` set, escape sequences in `buf` take up no width.
 pub fn nth_last_newline_pos(
     n: usize,
     buf: &str,
     screen_width: Option<i32>,
+    ansi: bool,
 ) -> usize {
     let mut offsets = Vec::new();
     let mut grapheme_count = 0;
 
-    for (offset, grapheme) in UnicodeSegmentation::grapheme_indices(buf, true) {
+    for (offset, grapheme) in ansi::graphemes(buf, ansi) {
+        if ansi && ansi::is_escape(grapheme) {
+            continue;
+        }
         grapheme_count += 1;
         if is_newline(grapheme)
             || grapheme_count >= screen_width.unwrap_or(i32::MAX)
@@ -90,16 +156,27 @@ pub fn make_valid(buf: Vec<u8>) -> Vec<u8> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_cursor_window() {
+        assert_eq!(cursor_window("abc", 3, 10), ((0, 3), 3));
+        assert_eq!(cursor_window("abcdef", 1, 4), ((0, 3), 1));
+        assert_eq!(cursor_window("abcdef", 6, 4), ((3, 6), 3));
+        // Wide characters take two columns each.
+        let text = "/\u{4f60}\u{597d}x";
+        assert_eq!(cursor_window(text, 4, 10), ((0, text.len()), 3));
+        assert_eq!(cursor_window(text, text.len(), 4), ((4, 8), 3));
+    }
+
     #[test]
     fn test_snd_last_newline_wrapped() {
         let s = "\n";
         let t = "";
         let u = "\naa\n";
         let v = "aaaaaa";
-        assert_eq!(nth_last_newline_pos(2, s, Some(3)), 0);
-        assert_eq!(nth_last_newline_pos(2, t, Some(3)), 0);
-        assert_eq!(nth_last_newline_pos(2, u, Some(3)), 1);
-        assert_eq!(nth_last_newline_pos(2, v, Some(3)), 3);
+        assert_eq!(nth_last_newline_pos(2, s, Some(3), false), 0);
+        assert_eq!(nth_last_newline_pos(2, t, Some(3), false), 0);
+        assert_eq!(nth_last_newline_pos(2, u, Some(3), false), 1);
+        assert_eq!(nth_last_newline_pos(2, v, Some(3), false), 3);
     }
 
     #[test]
@@ -110,18 +187,18 @@ mod tests {
         let v = "aaaaaa";
         let w = "\n\n\n\n\n\n\n\n\n\n";
         let x = "ฤๅหาใครค้ำชูกู้บรรลังก์ ฯ";
-        assert_eq!(nth_last_newline_pos(2, s, Some(3)), 0);
-        assert_eq!(nth_last_newline_pos(2, t, Some(3)), 0);
-        assert_eq!(nth_last_newline_pos(2, u, Some(3)), 1);
-        assert_eq!(nth_last_newline_pos(2, v, Some(3)), 3);
-        assert_eq!(nth_last_newline_pos(10, w, Some(3)), 1);
+        assert_eq!(nth_last_newline_pos(2, s, Some(3), false), 0);
+        assert_eq!(nth_last_newline_pos(2, t, Some(3), false), 0);
+        assert_eq!(nth_last_newline_pos(2, u, Some(3), false), 1);
+        assert_eq!(nth_last_newline_pos(2, v, Some(3), false), 3);
+        assert_eq!(nth_last_newline_pos(10, w, Some(3), false), 1);
         // When we give an incomplete grapheme
         assert!(std::panic::catch_unwind(|| {
-            nth_last_newline_pos(56, &x[1..], Some(131));
+            nth_last_newline_pos(56, &x[1..], Some(131), false);
         })
         .is_err());
         assert!(std::panic::catch_unwind(|| {
-            nth_last_newline_pos(56, &x[..x.len() - 1], Some(131));
+            nth_last_newline_pos(56, &x[..x.len() - 1], Some(131), false);
         })
         .is_err());
     }
@@ -145,7 +222,24 @@ mod tests {
             eprint!("{} ", c);
         }
 
-        assert_eq!(nth_last_newline_pos(1, &bible[..], Some(3)), 207);
+        assert_eq!(nth_last_newline_pos(1, &bible[..], Some(3), false), 207);
+    }
+
+    #[test]
+    fn test_newline_pos_ansi() {
+        let s = "\u{1b}[31maaaa\u{1b}[0m\nbb";
+        assert_eq!(nth_newline_pos(1, s, Some(4), true), 9);
+        assert_eq!(nth_newline_pos(2, s, Some(4), true), 14);
+        assert_eq!(nth_newline_pos(1, s, Some(4), false), 4);
+        assert_eq!(nth_last_newline_pos(1, s, Some(4), true), 14);
+        assert_eq!(nth_last_newline_pos(2, s, Some(4), true), 9);
+    }
+
+    #[test]
+    fn test_text_width() {
+        assert_eq!(text_width(80, false), 80);
+        assert_eq!(text_width(80, true), 80 - LINE_NUMBER_WIDTH);
+        assert_eq!(text_width(3, true), 1);
     }
 
     #[test]
@@ -155,12 +249,12 @@ mod tests {
         let u = "\naa\n";
         let v = "aaaaaa";
         let x = "\naaaa\naa\n";
-        assert_eq!(nth_newline_pos(1, s, Some(3)), 1);
-        assert_eq!(nth_newline_pos(1, t, Some(3)), 0);
-        assert_eq!(nth_newline_pos(1, u, Some(3)), 1);
-        assert_eq!(nth_newline_pos(1, v, Some(3)), 3);
-        assert_eq!(nth_newline_pos(2, x, Some(3)), 4);
-        assert_eq!(nth_newline_pos(2, s, Some(3)), 1);
+        assert_eq!(nth_newline_pos(1, s, Some(3), false), 1);
+        assert_eq!(nth_newline_pos(1, t, Some(3), false), 0);
+        assert_eq!(nth_newline_pos(1, u, Some(3), false), 1);
+        assert_eq!(nth_newline_pos(1, v, Some(3), false), 3);
+        assert_eq!(nth_newline_pos(2, x, Some(3), false), 4);
+        assert_eq!(nth_newline_pos(2, s, Some(3), false), 1);
     }
 
     #[test]
@@ -170,11 +264,11 @@ mod tests {
         let u = "\naa\n";
         let v = "aaaaaa";
         let x = "\naaaa\naa\n";
-        assert_eq!(nth_newline_pos(1, s, None), 1);
-        assert_eq!(nth_newline_pos(1, t, None), 0);
-        assert_eq!(nth_newline_pos(1, u, None), 1);
-        assert_eq!(nth_newline_pos(1, v, None), 6);
-        assert_eq!(nth_newline_pos(2, x, None), 6);
-        assert_eq!(nth_newline_pos(2, s, None), 1);
+        assert_eq!(nth_newline_pos(1, s, None, false), 1);
+        assert_eq!(nth_newline_pos(1, t, None, false), 0);
+        assert_eq!(nth_newline_pos(1, u, None, false), 1);
+        assert_eq!(nth_newline_pos(1, v, None, false), 6);
+        assert_eq!(nth_newline_pos(2, x, None, false), 6);
+        assert_eq!(nth_newline_pos(2, s, None, false), 1);
     }
 }